@@ -0,0 +1,175 @@
+#![no_std]
+use soroban_sdk::{
+    contract, contractclient, contracterror, contractimpl, contracttype, symbol_short, Address,
+    BytesN, Env, IntoVal, Symbol, TryFromVal, Val, Vec,
+};
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Error {
+    NotInitialized = 1,
+    AlreadyInitialized = 2,
+    WorldNotFound = 3,
+}
+
+/// The id of a world within the contract hosting it
+type WorldId = u32;
+
+/// Ledgers in a day, at roughly five seconds a ledger
+const DAY_IN_LEDGERS: u32 = 17280;
+
+/// The most results a single page will hold, a page always holds room for at least one
+const MAX_LIMIT: u32 = 100;
+
+/// The part of the world contract the factory calls
+#[contractclient(name = "WorldClient")]
+pub trait WorldInterface {
+    fn genesis(env: Env, admin: Address, name: Symbol) -> WorldId;
+}
+
+#[contracttype]
+enum FactoryKey {
+    Admin,
+    Wasm,
+    Count,
+    World(u32),
+    Name(Symbol),
+}
+
+/// A world deployed by the factory, `world` is the id to pass to the entry points of `contract`
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WorldEntry {
+    pub name: Symbol,
+    pub contract: Address,
+    pub world: WorldId,
+}
+
+/// A page of deployed worlds in the order they were deployed, `next` is the cursor to continue
+/// from
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WorldPage {
+    pub worlds: Vec<WorldEntry>,
+    pub next: Option<u32>,
+}
+
+/// Deploys a fresh world contract per match from an uploaded copy of the world wasm and keeps a
+/// registry of the worlds it deployed, each in its own persistent entry. Publishes
+/// `("factory", "deploy", name)` with the `WorldEntry` of each world
+#[contract]
+pub struct Factory;
+#[contractimpl]
+impl Factory {
+    /// Set the admin of the factory and the hash of the uploaded world wasm it deploys, ran once
+    /// and authorised by the admin. Deploy and initialise the factory in the same transaction so
+    /// nobody else gets to initialise it first
+    pub fn init(env: Env, admin: Address, wasm_hash: BytesN<32>) -> Result<(), Error> {
+        if env.storage().instance().has(&FactoryKey::Admin) {
+            return Err(Error::AlreadyInitialized);
+        }
+        admin.require_auth();
+        env.storage().instance().set(&FactoryKey::Admin, &admin);
+        env.storage().instance().set(&FactoryKey::Wasm, &wasm_hash);
+        extend_instance(&env);
+
+        Ok(())
+    }
+
+    /// Deploy a new world contract and run its genesis with `admin` and `name`, authorised by the
+    /// admin of the factory and the admin of the world. Each name can only be used once
+    pub fn deploy(env: Env, admin: Address, name: Symbol) -> Result<WorldEntry, Error> {
+        let factory_admin: Address = env
+            .storage()
+            .instance()
+            .get(&FactoryKey::Admin)
+            .ok_or(Error::NotInitialized)?;
+        factory_admin.require_auth();
+        admin.require_auth();
+        if env.storage().persistent().has(&FactoryKey::Name(name.clone())) {
+            return Err(Error::AlreadyInitialized);
+        }
+        let wasm_hash: BytesN<32> = env.storage().instance().get(&FactoryKey::Wasm).unwrap();
+
+        // the position in the registry keeps every salt, and so every address, unique
+        let position = count(&env);
+        let mut salt = [0u8; 32];
+        salt[..4].copy_from_slice(&position.to_be_bytes());
+        let contract = env
+            .deployer()
+            .with_current_contract(BytesN::from_array(&env, &salt))
+            .deploy(wasm_hash);
+        let world = WorldClient::new(&env, &contract).genesis(&admin, &name);
+
+        let entry = WorldEntry {
+            name: name.clone(),
+            contract,
+            world,
+        };
+        store_entry(&env, &FactoryKey::World(position), &entry);
+        store_entry(&env, &FactoryKey::Name(name.clone()), &position);
+        env.storage().instance().set(&FactoryKey::Count, &(position + 1));
+        extend_instance(&env);
+        env.events()
+            .publish((symbol_short!("factory"), symbol_short!("deploy"), name), entry.clone());
+
+        Ok(entry)
+    }
+
+    /// List the deployed worlds from the `cursor` position onwards, at most `limit` of them
+    pub fn list_worlds(env: Env, cursor: u32, limit: u32) -> WorldPage {
+        let limit = limit.clamp(1, MAX_LIMIT);
+        let mut worlds = Vec::new(&env);
+        let mut next = None;
+
+        for position in cursor..count(&env) {
+            if worlds.len() == limit {
+                next = Some(position);
+                break;
+            }
+            worlds.push_back(load_entry(&env, &FactoryKey::World(position)).unwrap());
+        }
+
+        WorldPage { worlds, next }
+    }
+
+    /// Get the world deployed under `name`
+    pub fn world_by_name(env: Env, name: Symbol) -> Result<WorldEntry, Error> {
+        let position: u32 =
+            load_entry(&env, &FactoryKey::Name(name)).ok_or(Error::WorldNotFound)?;
+
+        Ok(load_entry(&env, &FactoryKey::World(position)).unwrap())
+    }
+}
+
+/// The number of worlds deployed so far, the only part of the registry kept in the instance
+fn count(env: &Env) -> u32 {
+    env.storage().instance().get(&FactoryKey::Count).unwrap_or(0)
+}
+
+/// Read a persistent entry, extending its ttl if it exists
+fn load_entry<V: TryFromVal<Env, Val>>(env: &Env, key: &FactoryKey) -> Option<V> {
+    let value = env.storage().persistent().get(key)?;
+    env.storage()
+        .persistent()
+        .extend_ttl(key, 6 * DAY_IN_LEDGERS, 7 * DAY_IN_LEDGERS);
+    Some(value)
+}
+
+/// Write a persistent entry and extend its ttl
+fn store_entry<V: IntoVal<Env, Val>>(env: &Env, key: &FactoryKey, value: &V) {
+    env.storage().persistent().set(key, value);
+    env.storage()
+        .persistent()
+        .extend_ttl(key, 6 * DAY_IN_LEDGERS, 7 * DAY_IN_LEDGERS);
+}
+
+fn extend_instance(env: &Env) {
+    env.storage()
+        .instance()
+        .extend_ttl(6 * DAY_IN_LEDGERS, 7 * DAY_IN_LEDGERS);
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,89 @@
+#![cfg(test)]
+extern crate std;
+
+use super::*;
+use soroban_sdk::{symbol_short, testutils::Address as _, vec};
+
+/// The world contract built for wasm, see the README for how to build it
+fn world_wasm() -> std::vec::Vec<u8> {
+    let path = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../target/wasm32-unknown-unknown/release/soroban_ecs.wasm"
+    );
+    std::fs::read(path).unwrap_or_else(|_| panic!("{path} hasn't been built, see the README"))
+}
+
+mod world {
+    use soroban_sdk::{contractclient, Address, Env, Symbol};
+
+    /// The parts of the world contract the tests read back, only the client is used
+    #[allow(dead_code)]
+    #[contractclient(name = "Client")]
+    pub trait World {
+        fn get_admin(env: Env, id: u32) -> Address;
+        fn world_id(env: Env, name: Symbol) -> u32;
+    }
+}
+
+#[test]
+fn factory_needs_a_wasm_to_deploy() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let factory = FactoryClient::new(&env, &env.register_contract(None, Factory));
+    let admin = Address::generate(&env);
+
+    assert_eq!(
+        factory.try_deploy(&admin, &symbol_short!("First")),
+        Err(Ok(Error::NotInitialized))
+    );
+    assert_eq!(
+        factory.try_world_by_name(&symbol_short!("First")),
+        Err(Ok(Error::WorldNotFound))
+    );
+    assert_eq!(factory.list_worlds(&0, &10).worlds, Vec::new(&env));
+}
+
+#[test]
+#[ignore = "needs target/wasm32-unknown-unknown/release/soroban_ecs.wasm"]
+fn factory_deploys_worlds() {
+    let wasm = world_wasm();
+    let env = Env::default();
+    env.budget().reset_unlimited();
+    env.mock_all_auths();
+    let factory = FactoryClient::new(&env, &env.register_contract(None, Factory));
+    let owner = Address::generate(&env);
+    let admin = Address::generate(&env);
+
+    let wasm_hash = env.deployer().upload_contract_wasm(wasm.as_slice());
+    factory.init(&owner, &wasm_hash);
+    assert_eq!(
+        factory.try_init(&admin, &wasm_hash),
+        Err(Ok(Error::AlreadyInitialized))
+    );
+
+    // deploying needs both the factory admin and the world admin
+    let first = factory.deploy(&admin, &symbol_short!("First"));
+    let signers: std::vec::Vec<Address> = env.auths().into_iter().map(|(a, _)| a).collect();
+    assert!(signers.contains(&owner));
+    assert!(signers.contains(&admin));
+    let second = factory.deploy(&admin, &symbol_short!("Second"));
+    assert_ne!(first.contract, second.contract);
+    assert_eq!(
+        factory.try_deploy(&admin, &symbol_short!("First")),
+        Err(Ok(Error::AlreadyInitialized))
+    );
+
+    // each deployed world is a contract of its own
+    let client = world::Client::new(&env, &first.contract);
+    assert_eq!(client.get_admin(&first.world), admin);
+    assert_eq!(client.world_id(&symbol_short!("First")), first.world);
+
+    assert_eq!(factory.world_by_name(&symbol_short!("Second")), second);
+    let page = factory.list_worlds(&0, &1);
+    assert_eq!(page.worlds, vec![&env, first]);
+    assert_eq!(page.next, Some(1));
+    let page = factory.list_worlds(&1, &10);
+    assert_eq!(page.worlds, vec![&env, second]);
+    assert_eq!(page.next, None);
+    assert_eq!(factory.list_worlds(&0, &0).next, Some(1));
+}
@@ -0,0 +1,90 @@
+use soroban_sdk::{contracttype, Env, Vec};
+
+const WORD_BITS: u32 = u128::BITS;
+
+/// A growable set of component bits, stored as 128 bit words with no trailing empty words so
+/// that equal sets always compare (and key maps) the same
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+pub struct Bitmap {
+    words: Vec<u128>,
+}
+
+impl Bitmap {
+    pub fn new(env: &Env) -> Self {
+        Bitmap {
+            words: Vec::new(env),
+        }
+    }
+
+    pub fn from_bit(env: &Env, bit: u32) -> Self {
+        let mut bitmap = Self::new(env);
+        bitmap.set(bit);
+        bitmap
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    pub fn contains(&self, bit: u32) -> bool {
+        self.words
+            .get(bit / WORD_BITS)
+            .map(|word| word & (1 << (bit % WORD_BITS)) != 0)
+            .unwrap_or(false)
+    }
+
+    pub fn set(&mut self, bit: u32) {
+        let index = bit / WORD_BITS;
+        while self.words.len() <= index {
+            self.words.push_back(0);
+        }
+        let word = self.words.get_unchecked(index);
+        self.words.set(index, word | (1 << (bit % WORD_BITS)));
+    }
+
+    pub fn clear(&mut self, bit: u32) {
+        let index = bit / WORD_BITS;
+        if let Some(word) = self.words.get(index) {
+            self.words.set(index, word & !(1 << (bit % WORD_BITS)));
+            self.trim();
+        }
+    }
+
+    /// All bits set in either bitmap
+    pub fn union(&self, other: &Bitmap) -> Bitmap {
+        let (mut longer, shorter) = if self.words.len() >= other.words.len() {
+            (self.clone(), other)
+        } else {
+            (other.clone(), self)
+        };
+        for (index, word) in shorter.words.iter().enumerate() {
+            let index = index as u32;
+            longer.words.set(index, longer.words.get_unchecked(index) | word);
+        }
+        longer
+    }
+
+    /// Whether every bit of `other` is also set in this bitmap
+    pub fn contains_all(&self, other: &Bitmap) -> bool {
+        other.words.iter().enumerate().all(|(index, word)| {
+            let mine = self.words.get(index as u32).unwrap_or(0);
+            mine & word == word
+        })
+    }
+
+    /// Whether the two bitmaps share at least one bit
+    pub fn intersects(&self, other: &Bitmap) -> bool {
+        self.words
+            .iter()
+            .zip(other.words.iter())
+            .any(|(mine, theirs)| mine & theirs != 0)
+    }
+
+    /// Drop trailing empty words, bitmaps handed in by callers may not be trimmed yet
+    pub(crate) fn trim(&mut self) {
+        while let Some(0) = self.words.last() {
+            self.words.pop_back();
+        }
+    }
+}
@@ -1,212 +1,2030 @@
 #![no_std]
-use alloc::string::{String, ToString};
 use soroban_sdk::{
-    contract, contractimpl, contracttype, Address, Env, Map, Symbol, Vec
+    contract, contractclient, contracterror, contractimpl, contracttype, events::Topics,
+    symbol_short, vec, Address, Bytes, ConversionError, Env, IntoVal, Map, Symbol, TryFromVal, Val,
+    Vec,
 };
 
 extern crate alloc;
 
+mod bitmap;
+
+pub use bitmap::Bitmap;
+
 #[contracttype]
+#[derive(Clone)]
 enum DataKey {
-    Genesis,
-    World,
-    Register,
+    Worlds,
+    Name(Symbol),
+    World(WorldId),
+    Register(WorldId),
+    Admin(WorldId),
+    Owned(WorldId, Address),
+    OwnedChunk(WorldId, Address, Index),
+    Entity(WorldId, Index),
+    Change(WorldId, Index),
+    Archetype(WorldId, Bitmap),
+    ArchetypeChunk(WorldId, Bitmap, Index),
+    Free(WorldId, Index),
+    Config(WorldId),
+    Hooked(WorldId, Index),
+    Resources(WorldId),
+}
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Error {
+    NotInitialized = 1,
+    AlreadyInitialized = 2,
+    EntityNotFound = 3,
+    NoComponents = 4,
+    ComponentNotRegistered = 5,
+    MissingComponent = 6,
+    SystemConflict = 7,
+    SystemNotFound = 8,
+    Unauthorized = 9,
+    InvalidConfig = 10,
+    StageNotFound = 11,
+    SystemCycle = 12,
+    Reentrant = 13,
+    ResourceNotFound = 14,
+    WorldNotFound = 15,
+    ComponentInUse = 16,
 }
 
-type Bitmap = u128;
+type WorldId = u32;
 type Index = u128;
 type Query = Bitmap;
+type Generation = u32;
+
+/// An entity handle, the generation is bumped each time the slot at `index` is recycled so stale
+/// handles can be told apart from the entity currently living there
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Entity {
+    pub index: Index,
+    pub generation: Generation,
+}
+
+/// A record of an entity moving between component signatures
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Change {
+    pub entity: Entity,
+    pub before: Bitmap,
+    pub after: Bitmap,
+}
+
+/// Everything the world holds for a single entity, each entity lives in its own persistent entry
+/// so spawning or touching one never rewrites the others. `added` and `changed` hold the world
+/// clock at which each component was added and last written
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EntityRecord {
+    pub generation: Generation,
+    pub owner: Address,
+    pub bitmap: Bitmap,
+    pub components: Vec<Address>,
+    pub values: Map<Address, Bytes>,
+    pub added: Map<Address, u32>,
+    pub changed: Map<Address, u32>,
+}
+
+/// Narrows a query down to the entities with a component in `with`, or any component if `with`
+/// is empty, added or written at or after a reading of the world clock
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Filter {
+    Any,
+    AddedSince(u32),
+    ChangedSince(u32),
+}
+
+/// Which entities a system is handed, all those matching its query or only those whose
+/// components were added or written since the system last ran
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Detect {
+    All,
+    Added,
+    Changed,
+}
+
+/// Where a query starts and where each page of it leaves off, `At` holds the archetype and the
+/// slot within it to continue from and `Done` is handed back once there is nothing left to visit
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum QueryCursor {
+    Start,
+    At(Bitmap, Index),
+    Done,
+}
+
+/// A page of entities matching a query along with their components, `next` is the cursor to pass
+/// to continue from
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct QueryPage {
+    pub entities: Vec<(Entity, Vec<Address>)>,
+    pub next: QueryCursor,
+}
+
+/// How a system is registered with the world, several systems may share the same query. Systems
+/// run stage by stage and within a stage after every system in `after` and before every system in
+/// `before`, constraints on systems that aren't registered are ignored. `last_run` is the world
+/// clock the system last ran at, zero if it hasn't yet, and `resources` the resources the system
+/// may write
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SystemInfo {
+    pub query: Query,
+    pub stage: Symbol,
+    pub before: Vec<Address>,
+    pub after: Vec<Address>,
+    pub detect: Detect,
+    pub last_run: u32,
+    pub resources: Vec<Symbol>,
+}
+
+/// A page of entities along with their components, `next` is the slot to continue from
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EntityPage {
+    pub entities: Vec<(Entity, Vec<Address>)>,
+    pub next: Option<Index>,
+}
+
+/// A page of systems keyed by their address, `next` is the cursor to continue from
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SystemPage {
+    pub systems: Vec<(Address, SystemInfo)>,
+    pub next: Option<u32>,
+}
+
+/// A page of registered components keyed by their bit, `next` is the cursor to continue from
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ComponentPage {
+    pub components: Vec<(u32, Address)>,
+    pub next: Option<u32>,
+}
+
+/// A cheap summary of the world
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WorldInfo {
+    pub name: Symbol,
+    pub entities: u32,
+    pub components: u32,
+    pub systems: u32,
+    pub clock: u32,
+}
+
+/// A change a system asks the world to make once it has run, `Spawn` takes the owner of the new
+/// entity and its components
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Command {
+    Spawn(Address, Vec<Address>),
+    Despawn(Entity),
+    Insert(Entity, Vec<Address>),
+    Remove(Entity, Vec<Address>),
+    SetComponent(Entity, Address, Bytes),
+    SetResource(Symbol, Bytes),
+    RemoveResource(Symbol),
+}
+
+/// The interface a system contract implements. On every tick the world calls `run` with the
+/// entities matching the query of the system and the resources of the world, and applies the
+/// commands handed back. The world can't be called back into while it is running a system, so
+/// everything the system needs is passed in. The commands a system hands back are applied
+/// together or, if any of them is invalid, not at all
+#[contractclient(name = "SystemClient")]
+pub trait SystemInterface {
+    fn run(
+        env: Env,
+        world: Address,
+        entities: Vec<Entity>,
+        resources: Map<Symbol, Bytes>,
+    ) -> Vec<Command>;
+}
+
+/// The interface a hook contract implements. A component can name a hook to call once it lands
+/// on an entity, `on_add`, and one to call once it is taken off or the entity is despawned,
+/// `on_remove`. Like systems, hooks can't call back into the world and hand back commands
+/// instead, which may not touch the entity the hook runs for
+#[contractclient(name = "HookClient")]
+pub trait HookInterface {
+    fn on_add(env: Env, world: Address, entity: Entity, component: Address) -> Vec<Command>;
+    fn on_remove(env: Env, world: Address, entity: Entity, component: Address) -> Vec<Command>;
+}
+
+/// Which of the hooks of a component to call
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Hook {
+    OnAdd,
+    OnRemove,
+}
+
+/// Ledgers in a day, at roughly five seconds a ledger
+const DAY_IN_LEDGERS: u32 = 17280;
+
+/// How long world entries are kept alive. Whenever an entry is touched with fewer than
+/// `ttl_threshold` ledgers left to live, it is extended to live for `ttl_extend_to` ledgers
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WorldConfig {
+    pub ttl_threshold: u32,
+    pub ttl_extend_to: u32,
+}
+
+/// The most results a single page will hold, a page always holds room for at least one
+const MAX_LIMIT: u32 = 100;
 
+/// A world hosted by the contract, its `clock` advances each time a system runs and stamps every
+/// component change. `free` is the slot despawned last, or 0 if there is none, and `archetypes`
+/// the signatures holding at least one entity in signature order
 #[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct World {
-    name: String,
+    id: WorldId,
+    name: Symbol,
     counter: Index,
-    entities: Map<Index, (Bitmap, Vec<Address>)>,
-    systems: Map<Query, Address>
+    alive: u32,
+    free: Index,
+    archetypes: Vec<Bitmap>,
+    systems: Map<Address, SystemInfo>,
+    stages: Vec<Symbol>,
+    clock: u32,
+}
+
+/// A despawned slot waiting to be reused, linking to the slot freed before it so the free list
+/// lives in the slots themselves
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct FreeSlot {
+    generation: Generation,
+    next: Index,
+}
+
+/// Entity slots per chunk of a slot set
+const CHUNK_SLOTS: Index = 128;
+
+/// A set of entity slots kept as bits in chunks of `CHUNK_SLOTS` slots, each chunk in an entry of
+/// its own, so adding or taking out a slot only touches its chunk and the set is walked in slot
+/// order. `chunks` lists the chunks holding at least one slot
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct SlotSet {
+    len: u32,
+    chunks: Vec<Index>,
+}
+
+/// The slot sets of a world and where they are kept
+#[derive(Clone)]
+enum Slots {
+    Archetype(WorldId, Bitmap),
+    Owned(WorldId, Address),
 }
 
 trait Registered {
-    fn register(env: &Env, address: Address) -> Option<Bitmap>;
-    fn unregister(env: &Env, system: Address);
+    fn register(env: &Env, id: WorldId, address: Address) -> u32;
+    fn lookup(env: &Env, id: WorldId, address: Address) -> Option<u32>;
+    fn resolve(env: &Env, id: WorldId, bits: &Bitmap) -> Vec<Address>;
+    fn list(env: &Env, id: WorldId, cursor: u32, limit: u32) -> ComponentPage;
+    fn count(env: &Env, id: WorldId) -> u32;
+    fn unregister(env: &Env, id: WorldId, system: Address) -> Result<(), Error>;
+    fn set_hooks(
+        env: &Env,
+        id: WorldId,
+        address: Address,
+        on_add: Option<Address>,
+        on_remove: Option<Address>,
+    ) -> u32;
+    fn hook(env: &Env, id: WorldId, address: Address, hook: Hook) -> Option<Address>;
 }
 
 trait System {
-    fn add_system(self, query: Query, address: Address) -> Self;
-    fn remove_system(self, query: Query) -> Self;
+    fn add_system(self, env: &Env, system: Address, info: SystemInfo) -> Result<Self, Error>
+    where
+        Self: Sized;
+    fn remove_system(self, system: Address) -> Result<Self, Error>
+    where
+        Self: Sized;
+    fn set_stages(self, env: &Env, stages: Vec<Symbol>) -> Result<Self, Error>
+    where
+        Self: Sized;
+    fn schedule(&self, env: &Env) -> Result<Vec<Address>, Error>;
 }
 
 impl System for World {
-    fn add_system(mut self, query: Query, system: Address) -> Self {
-        self.systems.set(query, system);
-        self
+    fn add_system(
+        mut self,
+        env: &Env,
+        system: Address,
+        mut info: SystemInfo,
+    ) -> Result<Self, Error> {
+        info.query.trim();
+        if self.systems.contains_key(system.clone()) {
+            return Err(Error::SystemConflict);
+        }
+        if !self.stages.contains(&info.stage) {
+            return Err(Error::StageNotFound);
+        }
+        self.systems.set(system, info);
+        self.schedule(env)?;
+        Ok(self)
+    }
+
+    fn remove_system(mut self, system: Address) -> Result<Self, Error> {
+        if self.systems.remove(system).is_none() {
+            return Err(Error::SystemNotFound);
+        }
+        Ok(self)
     }
 
-    fn remove_system(mut self, query: Query) -> Self {
-        self.systems.remove(query);
-        self
+    /// Replace the stages in the order they run, every stage a system runs in must remain and the
+    /// systems must still have an order to run in
+    fn set_stages(mut self, env: &Env, stages: Vec<Symbol>) -> Result<Self, Error> {
+        for (position, stage) in stages.iter().enumerate() {
+            if stages.first_index_of(&stage) != Some(position as u32) {
+                return Err(Error::InvalidConfig);
+            }
+        }
+        for (_, info) in self.systems.iter() {
+            if !stages.contains(&info.stage) {
+                return Err(Error::StageNotFound);
+            }
+        }
+        self.stages = stages;
+        self.schedule(env)?;
+        Ok(self)
+    }
+
+    /// The order systems run in. Stages run one after the other and within a stage the first
+    /// system by address whose `after` constraints are met goes next, so the order is the same on
+    /// every tick. Fails if the constraints can't all be met
+    fn schedule(&self, env: &Env) -> Result<Vec<Address>, Error> {
+        let mut preceding: Map<Address, Vec<Address>> = Map::new(env);
+        for (system, info) in self.systems.iter() {
+            for other in info.after.iter() {
+                if self.systems.contains_key(other.clone()) {
+                    let mut list = preceding.get(system.clone()).unwrap_or(Vec::new(env));
+                    list.push_back(other);
+                    preceding.set(system.clone(), list);
+                }
+            }
+            for other in info.before.iter() {
+                if self.systems.contains_key(other.clone()) {
+                    let mut list = preceding.get(other.clone()).unwrap_or(Vec::new(env));
+                    list.push_back(system.clone());
+                    preceding.set(other, list);
+                }
+            }
+        }
+
+        let mut order = Vec::new(env);
+        for stage in self.stages.iter() {
+            let mut pending = Vec::new(env);
+            for (system, info) in self.systems.iter() {
+                if info.stage == stage {
+                    pending.push_back(system);
+                }
+            }
+
+            while !pending.is_empty() {
+                // anything still waiting on a pending system or one in a later stage is a cycle
+                let position = pending
+                    .iter()
+                    .position(|system| {
+                        preceding
+                            .get(system)
+                            .unwrap_or(Vec::new(env))
+                            .iter()
+                            .all(|other| order.contains(&other))
+                    })
+                    .ok_or(Error::SystemCycle)? as u32;
+                order.push_back(pending.get_unchecked(position));
+                pending.remove(position);
+            }
+        }
+
+        Ok(order)
     }
 }
 
 impl World {
-    fn spawn<R: Registered>(mut self, env: &Env, components: Vec<Address>) -> (bool, Self) {
-        let mut bitmap = None;
-        let mut filtered_components = Vec::new(&env);
+    /// Entities holding every component in `with` and none in `without` that pass `filter`, from
+    /// `cursor` onwards. Only archetypes whose signature satisfies the query are visited, in
+    /// signature order and each in slot order, so entities left alone keep their place between
+    /// pages
+    fn query(
+        &self,
+        env: &Env,
+        with: &Bitmap,
+        without: &Bitmap,
+        filter: &Filter,
+        cursor: QueryCursor,
+        limit: u32,
+    ) -> QueryPage {
+        let limit = limit.clamp(1, MAX_LIMIT);
+        let mut entities = Vec::new(env);
+        let watched = self.watched(env, with, filter);
 
-        for component in components.into_iter() {
-            if let Some(a) = R::register(env, component.clone()) {
-                bitmap = match bitmap {
-                    None => Some(a),
-                    Some(b) => Some(a + b),
+        for signature in self.archetypes.iter() {
+            if !signature.contains_all(with) || signature.intersects(without) {
+                continue;
+            }
+            let from = match &cursor {
+                QueryCursor::Start => 0,
+                QueryCursor::At(archetype, _) if signature < *archetype => continue,
+                QueryCursor::At(archetype, slot) if signature == *archetype => *slot,
+                QueryCursor::At(..) => 0,
+                QueryCursor::Done => break,
+            };
+            for index in Slots::Archetype(self.id, signature.clone()).iter(env, from) {
+                let (entity, record) = Self::member(env, self.id, index);
+                if !record.matches(&watched, filter) {
+                    continue;
+                }
+                if entities.len() == limit {
+                    return QueryPage {
+                        entities,
+                        next: QueryCursor::At(signature, index),
+                    };
+                }
+                entities.push_back((entity, record.components));
+            }
+        }
+
+        QueryPage {
+            entities,
+            next: QueryCursor::Done,
+        }
+    }
+
+    /// Every entity holding all the components in `with` that passes `filter`
+    fn members(&self, env: &Env, with: &Bitmap, filter: &Filter) -> Vec<Entity> {
+        let mut entities = Vec::new(env);
+        let watched = self.watched(env, with, filter);
+        for signature in self.archetypes.iter() {
+            if !signature.contains_all(with) {
+                continue;
+            }
+            for index in Slots::Archetype(self.id, signature).iter(env, 0) {
+                let (entity, record) = Self::member(env, self.id, index);
+                if record.matches(&watched, filter) {
+                    entities.push_back(entity);
+                }
+            }
+        }
+        entities
+    }
+
+    /// The entities of the world slot by slot from `cursor` onwards, despawned slots are passed
+    /// over
+    fn list_entities(&self, env: &Env, cursor: Index, limit: u32) -> EntityPage {
+        let limit = limit.clamp(1, MAX_LIMIT);
+        let mut entities = Vec::new(env);
+
+        for index in cursor.max(1)..=self.counter {
+            if entities.len() == limit {
+                return EntityPage {
+                    entities,
+                    next: Some(index),
                 };
+            }
+            if let Some(record) = EntityRecord::load_slot(env, self.id, index) {
+                let entity = Entity {
+                    index,
+                    generation: record.generation,
+                };
+                entities.push_back((entity, record.components));
+            }
+        }
+
+        EntityPage { entities, next: None }
+    }
+
+    /// The components `filter` looks at, resolved once per read rather than once per entity.
+    /// `None` when every component counts
+    fn watched(&self, env: &Env, with: &Bitmap, filter: &Filter) -> Option<Vec<Address>> {
+        if with.is_empty() || *filter == Filter::Any {
+            return None;
+        }
+        Some(Register::resolve(env, self.id, with))
+    }
+
+    /// Load the entity living in a slot found in an archetype or owner
+    fn member(env: &Env, id: WorldId, index: Index) -> (Entity, EntityRecord) {
+        let record =
+            EntityRecord::load_slot(env, id, index).expect("slot sets only hold living slots");
+        let entity = Entity {
+            index,
+            generation: record.generation,
+        };
+        (entity, record)
+    }
+
+    /// Add the entity in slot `index` to the archetype of `signature`
+    fn enter_archetype(&mut self, env: &Env, batch: &mut Batch, signature: &Bitmap, index: Index) {
+        if Slots::Archetype(self.id, signature.clone()).insert(env, batch, index) {
+            let position = self.archetypes.binary_search(signature).unwrap_err();
+            self.archetypes.insert(position, signature.clone());
+        }
+    }
+
+    /// Take the entity in slot `index` out of the archetype of `signature`, dropping the archetype
+    /// once it is empty
+    fn leave_archetype(&mut self, env: &Env, batch: &mut Batch, signature: &Bitmap, index: Index) {
+        if Slots::Archetype(self.id, signature.clone()).remove(env, batch, index) {
+            let position = self.archetypes.binary_search(signature).unwrap();
+            self.archetypes.remove(position);
+        }
+    }
+
+    /// Move the entity in slot `index` between archetypes as its signature changes
+    fn move_archetype(
+        &mut self,
+        env: &Env,
+        batch: &mut Batch,
+        index: Index,
+        before: &Bitmap,
+        after: &Bitmap,
+    ) {
+        if before != after {
+            self.leave_archetype(env, batch, before, index);
+            self.enter_archetype(env, batch, after, index);
+        }
+    }
+
+    fn list_systems(&self, env: &Env, cursor: u32, limit: u32) -> SystemPage {
+        let limit = limit.clamp(1, MAX_LIMIT);
+        let mut systems = Vec::new(env);
+        let mut next = None;
+
+        for (position, system) in self.systems.iter().enumerate().skip(cursor as usize) {
+            if systems.len() == limit {
+                next = Some(position as u32);
+                break;
+            }
+            systems.push_back(system);
+        }
+
+        SystemPage { systems, next }
+    }
+
+    fn spawn<R: Registered>(
+        mut self,
+        env: &Env,
+        batch: &mut Batch,
+        owner: Address,
+        components: Vec<Address>,
+    ) -> Result<(Entity, Self), Error> {
+        let mut bitmap = Bitmap::new(env);
+        let mut filtered_components = Vec::new(env);
+
+        for component in components.into_iter() {
+            if !filtered_components.contains(&component) {
+                let bit = R::lookup(env, self.id, component.clone());
+                bitmap.set(bit.ok_or(Error::ComponentNotRegistered)?);
                 filtered_components.push_back(component);
             }
         }
 
-        if let Some(bitmap) = bitmap {
-            self.counter = self.counter + 1;
-            self.entities.set(self.counter, (bitmap, filtered_components));
-            return (true, self);
+        if bitmap.is_empty() {
+            return Err(Error::NoComponents);
+        }
+
+        let entity = if self.free == 0 {
+            self.counter += 1;
+            Entity {
+                index: self.counter,
+                generation: 0,
+            }
+        } else {
+            let key = DataKey::Free(self.id, self.free);
+            let slot: FreeSlot = load_entry(env, self.id, &key).expect("free slots are linked");
+            batch.remove(env, &key);
+            let entity = Entity {
+                index: self.free,
+                generation: slot.generation,
+            };
+            self.free = slot.next;
+            entity
+        };
+        let mut added = Map::new(env);
+        for component in filtered_components.iter() {
+            added.set(component, self.clock);
+        }
+        self.enter_archetype(env, batch, &bitmap, entity.index);
+        let record = EntityRecord {
+            generation: entity.generation,
+            owner,
+            bitmap,
+            components: filtered_components,
+            values: Map::new(env),
+            added: added.clone(),
+            changed: added,
+        };
+        record.store(env, batch, self.id, entity.index);
+        self.alive += 1;
+        batch.publish(
+            env,
+            (symbol_short!("entity"), symbol_short!("spawn"), self.id, record.owner),
+            (entity, record.bitmap),
+        );
+
+        Ok((entity, self))
+    }
+
+    fn despawn(
+        mut self,
+        env: &Env,
+        batch: &mut Batch,
+        entity: Entity,
+    ) -> Result<(EntityRecord, Self), Error> {
+        let record = EntityRecord::load(env, self.id, entity)?;
+
+        batch.remove(env, &DataKey::Entity(self.id, entity.index));
+        batch.remove(env, &DataKey::Change(self.id, entity.index));
+        self.leave_archetype(env, batch, &record.bitmap, entity.index);
+        let slot = FreeSlot {
+            generation: entity.generation + 1,
+            next: self.free,
+        };
+        batch.store(env, self.id, &DataKey::Free(self.id, entity.index), &slot);
+        self.free = entity.index;
+        self.alive -= 1;
+        batch.publish(
+            env,
+            (symbol_short!("entity"), symbol_short!("despawn"), self.id, record.owner.clone()),
+            (entity, record.bitmap.clone()),
+        );
+
+        Ok((record, self))
+    }
+}
+
+impl Slots {
+    fn id(&self) -> WorldId {
+        match self {
+            Slots::Archetype(id, _) | Slots::Owned(id, _) => *id,
+        }
+    }
+
+    fn key(&self) -> DataKey {
+        match self {
+            Slots::Archetype(id, signature) => DataKey::Archetype(*id, signature.clone()),
+            Slots::Owned(id, owner) => DataKey::Owned(*id, owner.clone()),
+        }
+    }
+
+    fn chunk_key(&self, chunk: Index) -> DataKey {
+        match self {
+            Slots::Archetype(id, signature) => {
+                DataKey::ArchetypeChunk(*id, signature.clone(), chunk)
+            }
+            Slots::Owned(id, owner) => DataKey::OwnedChunk(*id, owner.clone(), chunk),
+        }
+    }
+
+    /// Add a slot to the set, returning whether the set was empty before
+    fn insert(&self, env: &Env, batch: &mut Batch, index: Index) -> bool {
+        let id = self.id();
+        let mut set = load_entry(env, id, &self.key()).unwrap_or_else(|| SlotSet {
+            len: 0,
+            chunks: Vec::new(env),
+        });
+        let chunk = index / CHUNK_SLOTS;
+        let key = self.chunk_key(chunk);
+        let bits: u128 = load_entry(env, id, &key).unwrap_or(0);
+        if bits == 0 {
+            let position = set.chunks.binary_search(chunk).unwrap_err();
+            set.chunks.insert(position, chunk);
+        }
+        batch.store(env, id, &key, &(bits | 1 << (index % CHUNK_SLOTS)));
+        set.len += 1;
+        batch.store(env, id, &self.key(), &set);
+
+        set.len == 1
+    }
+
+    /// Take a slot out of the set, returning whether the set is empty now
+    fn remove(&self, env: &Env, batch: &mut Batch, index: Index) -> bool {
+        let id = self.id();
+        let mut set: SlotSet = load_entry(env, id, &self.key()).expect("the slot is in the set");
+        let chunk = index / CHUNK_SLOTS;
+        let key = self.chunk_key(chunk);
+        let bits = load_entry::<u128>(env, id, &key).unwrap() & !(1 << (index % CHUNK_SLOTS));
+        if bits == 0 {
+            batch.remove(env, &key);
+            set.chunks.remove(set.chunks.binary_search(chunk).unwrap());
+        } else {
+            batch.store(env, id, &key, &bits);
+        }
+        set.len -= 1;
+        if set.len == 0 {
+            batch.remove(env, &self.key());
+        } else {
+            batch.store(env, id, &self.key(), &set);
+        }
+
+        set.len == 0
+    }
+
+    /// Keep the set and the chunk holding `index` alive
+    fn extend(&self, env: &Env, index: Index) {
+        load_entry::<SlotSet>(env, self.id(), &self.key());
+        load_entry::<u128>(env, self.id(), &self.chunk_key(index / CHUNK_SLOTS));
+    }
+
+    /// The slots in the set from `from` onwards, in slot order, loading each chunk as it is
+    /// reached
+    fn iter(&self, env: &Env, from: Index) -> impl Iterator<Item = Index> {
+        let set: Option<SlotSet> = load_entry(env, self.id(), &self.key());
+        let chunks = set.map(|set| set.chunks).unwrap_or_else(|| Vec::new(env));
+        let (env, slots) = (env.clone(), self.clone());
+
+        chunks
+            .into_iter()
+            .filter(move |chunk| (chunk + 1) * CHUNK_SLOTS > from)
+            .flat_map(move |chunk| {
+                let bits: u128 = load_entry(&env, slots.id(), &slots.chunk_key(chunk)).unwrap();
+                (0..CHUNK_SLOTS)
+                    .filter(move |slot| bits & 1 << slot != 0)
+                    .map(move |slot| chunk * CHUNK_SLOTS + slot)
+            })
+            .filter(move |index| *index >= from)
+    }
+}
+
+impl EntityRecord {
+    fn load_slot(env: &Env, id: WorldId, index: Index) -> Option<Self> {
+        load_entry(env, id, &DataKey::Entity(id, index))
+    }
+
+    /// Load the entity, rejecting handles whose generation no longer matches their slot
+    fn load(env: &Env, id: WorldId, entity: Entity) -> Result<Self, Error> {
+        Self::load_slot(env, id, entity.index)
+            .filter(|record| record.generation == entity.generation)
+            .ok_or(Error::EntityNotFound)
+    }
+
+    fn store(&self, env: &Env, batch: &mut Batch, id: WorldId, index: Index) {
+        batch.store(env, id, &DataKey::Entity(id, index), self);
+    }
+
+    fn insert_components<R: Registered>(
+        &mut self,
+        env: &Env,
+        batch: &mut Batch,
+        id: WorldId,
+        entity: Entity,
+        components: Vec<Address>,
+        now: u32,
+    ) -> Result<bool, Error> {
+        let before = self.bitmap.clone();
+        let mut updated = false;
+
+        for component in components.into_iter() {
+            if self.components.contains(&component) {
+                continue;
+            }
+            let bit = R::lookup(env, id, component.clone()).ok_or(Error::ComponentNotRegistered)?;
+            self.bitmap.set(bit);
+            self.added.set(component.clone(), now);
+            self.changed.set(component.clone(), now);
+            self.components.push_back(component);
+            updated = true;
+        }
+
+        if updated {
+            self.record_change(env, batch, id, entity, before);
+        }
+
+        Ok(updated)
+    }
+
+    fn remove_components<R: Registered>(
+        &mut self,
+        env: &Env,
+        batch: &mut Batch,
+        id: WorldId,
+        entity: Entity,
+        components: Vec<Address>,
+    ) -> bool {
+        let before = self.bitmap.clone();
+        let mut updated = false;
+
+        for component in components.into_iter() {
+            if let Some(position) = self.components.first_index_of(&component) {
+                if let Some(bit) = R::lookup(env, id, component.clone()) {
+                    self.bitmap.clear(bit);
+                }
+                self.components.remove(position);
+                self.added.remove(component.clone());
+                self.changed.remove(component.clone());
+                self.values.remove(component);
+                updated = true;
+            }
+        }
+
+        if updated {
+            self.record_change(env, batch, id, entity, before);
+        }
+
+        updated
+    }
+
+    fn get_component(&self, component: Address) -> Result<Option<Bytes>, Error> {
+        if !self.components.contains(&component) {
+            return Err(Error::MissingComponent);
+        }
+
+        Ok(self.values.get(component))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn set_component(
+        &mut self,
+        env: &Env,
+        batch: &mut Batch,
+        id: WorldId,
+        entity: Entity,
+        component: Address,
+        value: Bytes,
+        now: u32,
+    ) -> Result<(), Error> {
+        if !self.components.contains(&component) {
+            return Err(Error::MissingComponent);
         }
 
-        (false, self)
+        self.changed.set(component.clone(), now);
+        self.values.set(component.clone(), value.clone());
+        batch.publish(
+            env,
+            (symbol_short!("entity"), symbol_short!("set"), id),
+            (entity, component, value),
+        );
+
+        Ok(())
+    }
+
+    /// Whether a component in `watched`, or any component if there is none to watch, passes
+    /// `filter`
+    fn matches(&self, watched: &Option<Vec<Address>>, filter: &Filter) -> bool {
+        let (stamps, since) = match filter {
+            Filter::Any => return true,
+            Filter::AddedSince(since) => (&self.added, *since),
+            Filter::ChangedSince(since) => (&self.changed, *since),
+        };
+
+        self.components.iter().any(|component| {
+            watched.as_ref().map_or(true, |watched| watched.contains(&component))
+                && stamps.get(component).unwrap_or(0) >= since
+        })
     }
 
-    fn despawn<R: Registered>(self, env: &Env, component: Address) -> Self {
-        R::unregister(env, component);
-        self
+    /// Keep the latest signature change of the entity next to it
+    fn record_change(
+        &self,
+        env: &Env,
+        batch: &mut Batch,
+        id: WorldId,
+        entity: Entity,
+        before: Bitmap,
+    ) {
+        let change = Change {
+            entity,
+            before,
+            after: self.bitmap.clone(),
+        };
+        batch.store(env, id, &DataKey::Change(id, entity.index), &change);
     }
 }
+/// The registered components of a world, indexed both by bit and by address
 #[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Register {
-    counter: Bitmap,
-    addresses: Vec<Address>,
-    map: Map<Bitmap, Address>,
+    counter: u32,
+    bits: Map<Address, u32>,
+    map: Map<u32, Address>,
+    on_add: Map<Address, Address>,
+    on_remove: Map<Address, Address>,
 }
 
 impl Registered for Register {
-    fn register(env: &Env, address: Address) -> Option<Bitmap> {
+    /// Register a component, returning its bit. Components already registered keep their bit
+    fn register(env: &Env, id: WorldId, address: Address) -> u32 {
+        if let Some(bit) = Self::lookup(env, id, address.clone()) {
+            return bit;
+        }
+
         let mut register: Register =
-            env.storage()
-                .instance()
-                .get(&DataKey::Register)
-                .unwrap_or_else(|| Register {
-                    counter: 0,
-                    addresses: Vec::new(env),
-                    map: Map::new(env),
-                });
+            load_entry(env, id, &DataKey::Register(id)).unwrap_or_else(|| Register {
+                counter: 0,
+                bits: Map::new(env),
+                map: Map::new(env),
+                on_add: Map::new(env),
+                on_remove: Map::new(env),
+            });
+
+        let bit = register.counter;
+        register.counter += 1;
+        register.bits.set(address.clone(), bit);
+        register.map.set(bit, address.clone());
+        store_entry(env, id, &DataKey::Register(id), &register);
+        env.events().publish(
+            (symbol_short!("component"), symbol_short!("register"), id, address),
+            bit,
+        );
 
-        if !register.addresses.contains(address.clone()) {
-            register.counter = register.counter + 1;
-            register.addresses.push_back(address.clone());
-            register.map.set(register.counter, address);
+        bit
+    }
+
+    fn lookup(env: &Env, id: WorldId, address: Address) -> Option<u32> {
+        let register: Register = load_entry(env, id, &DataKey::Register(id))?;
+        register.bits.get(address)
+    }
 
-            return Some(1 << register.counter);
+    /// The registered components whose bit is in `bits`
+    fn resolve(env: &Env, id: WorldId, bits: &Bitmap) -> Vec<Address> {
+        let mut components = Vec::new(env);
+        let register: Option<Register> = load_entry(env, id, &DataKey::Register(id));
+        for (bit, address) in register.iter().flat_map(|register| register.map.iter()) {
+            if bits.contains(bit) {
+                components.push_back(address);
+            }
+        }
+        components
+    }
+
+    fn list(env: &Env, id: WorldId, cursor: u32, limit: u32) -> ComponentPage {
+        let limit = limit.clamp(1, MAX_LIMIT);
+        let mut components = Vec::new(env);
+        let mut next = None;
+
+        let register: Option<Register> = load_entry(env, id, &DataKey::Register(id));
+        for (bit, address) in register.iter().flat_map(|register| register.map.iter()) {
+            if bit < cursor {
+                continue;
+            }
+            if components.len() == limit {
+                next = Some(bit);
+                break;
+            }
+            components.push_back((bit, address));
         }
 
-        None
+        ComponentPage { components, next }
     }
 
-    fn unregister(env: &Env, address: Address) {
+    fn count(env: &Env, id: WorldId) -> u32 {
+        load_entry::<Register>(env, id, &DataKey::Register(id))
+            .map(|register| register.map.len())
+            .unwrap_or(0)
+    }
+
+    fn unregister(env: &Env, id: WorldId, address: Address) -> Result<(), Error> {
         let mut register: Register =
-            env.storage()
-                .instance()
-                .get(&DataKey::Register)
-                .expect("best to have a register before we unregister!");
+            load_entry(env, id, &DataKey::Register(id)).ok_or(Error::ComponentNotRegistered)?;
+
+        let bit = register.bits.get(address.clone()).ok_or(Error::ComponentNotRegistered)?;
+        register.bits.remove(address.clone());
+        register.map.remove(bit);
+        register.on_add.remove(address.clone());
+        register.on_remove.remove(address.clone());
+        store_entry(env, id, &DataKey::Register(id), &register);
+        env.events().publish(
+            (symbol_short!("component"), Symbol::new(env, "unregister"), id, address),
+            bit,
+        );
+
+        Ok(())
+    }
+
+    /// Register a component if it isn't already and replace the hooks it calls
+    fn set_hooks(
+        env: &Env,
+        id: WorldId,
+        address: Address,
+        on_add: Option<Address>,
+        on_remove: Option<Address>,
+    ) -> u32 {
+        let bit = Self::register(env, id, address.clone());
+        let key = DataKey::Register(id);
+        let mut register: Register = load_entry(env, id, &key).unwrap();
 
-        if let Ok(index) = register.addresses.binary_search(address.clone()) {
-            register.addresses.remove(index);
+        match on_add.clone() {
+            Some(hook) => register.on_add.set(address.clone(), hook),
+            None => {
+                register.on_add.remove(address.clone());
+            }
+        }
+        match on_remove.clone() {
+            Some(hook) => register.on_remove.set(address.clone(), hook),
+            None => {
+                register.on_remove.remove(address.clone());
+            }
+        }
+        store_entry(env, id, &key, &register);
+        env.events().publish(
+            (symbol_short!("component"), symbol_short!("hooks"), id, address),
+            (on_add, on_remove),
+        );
+
+        bit
+    }
+
+    fn hook(env: &Env, id: WorldId, address: Address, hook: Hook) -> Option<Address> {
+        let register: Register = load_entry(env, id, &DataKey::Register(id))?;
+
+        match hook {
+            Hook::OnAdd => register.on_add.get(address),
+            Hook::OnRemove => register.on_remove.get(address),
         }
     }
 }
 
+/// The world contract, hosting any number of named worlds told apart by their id. Every
+/// mutation publishes an event, topics first and data second
+///
+/// - `("world", "genesis", id)`: `(admin, name)`
+/// - `("world", "admin", id)`: the new admin, `("world", "config", id)`: the `WorldConfig`,
+///   `("world", "stages", id)`: the stages
+/// - `("entity", "spawn", id, owner)` and `("entity", "despawn", id, owner)`: `(entity, bitmap)`
+/// - `("entity", "insert", id)` and `("entity", "remove", id)`: `(entity, before, after)`
+/// - `("entity", "set", id)`: `(entity, component, value)`
+/// - `("entity", "transfer", id, owner)`: `(entity, new_owner)`
+/// - `("component", "register", id, component)` and `("component", "unregister", id,
+///   component)`: the bit of the component
+/// - `("component", "hooks", id, component)`: `(on_add, on_remove)`
+/// - `("system", "add", id, system)`: the `SystemInfo` of the system, `("system", "remove", id,
+///   system)`
+/// - `("system", "grant", id, system)`: the resources the system may write
+/// - `("system", "run", id, system)`: the clock the system ran at, `("system", "dropped", id,
+///   system)`: the error the batch of the system was rolled back on
+/// - `("resource", "set", id, key)`: the value, `("resource", "remove", id, key)`
 #[contract]
 pub struct Contract;
 #[contractimpl]
 impl Contract {
 
-    fn check_genesis(env: &Env) -> bool {
-        env.storage().instance().get(&DataKey::Genesis).unwrap_or(false)
-    }
-    /// The genesis of the world, ran once, in which we set a name for the world
-    pub fn genesis(env: Env, name: Symbol) {
-        if !Self::check_genesis(&env) {
-            env.storage().instance().set(&DataKey::Genesis, &true);
-            let world = World {
-                name: name.to_string(),
-                entities: Map::new(&env),
-                counter: Default::default(),
-                systems: Map::new(&env),
+    fn check_genesis(env: &Env, id: WorldId) -> bool {
+        env.storage().persistent().has(&DataKey::World(id))
+    }
+    /// The genesis of a world, in which we set a name and an admin for it, returning the id every
+    /// other entry point takes. Each name can only be used once
+    pub fn genesis(env: Env, admin: Address, name: Symbol) -> Result<WorldId, Error> {
+        if env.storage().persistent().has(&DataKey::Name(name.clone())) {
+            return Err(Error::AlreadyInitialized);
+        }
+        admin.require_auth();
+
+        let id: WorldId = env.storage().instance().get(&DataKey::Worlds).unwrap_or(0);
+        env.storage().instance().set(&DataKey::Worlds, &(id + 1));
+        store_entry(&env, id, &DataKey::Name(name.clone()), &id);
+        store_entry(&env, id, &DataKey::Admin(id), &admin);
+        let world = World {
+            id,
+            name,
+            counter: Default::default(),
+            alive: 0,
+            free: 0,
+            archetypes: Vec::new(&env),
+            systems: Map::new(&env),
+            stages: vec![&env, symbol_short!("update")],
+            clock: 1,
+        };
+        store_entry(&env, id, &DataKey::World(id), &world);
+        extend_instance(&env, id);
+        env.events()
+            .publish((symbol_short!("world"), symbol_short!("genesis"), id), (admin, world.name));
+
+        Ok(id)
+    }
+
+    /// Get the id of the world named `name`
+    pub fn world_id(env: Env, name: Symbol) -> Result<WorldId, Error> {
+        let key = DataKey::Name(name);
+        let id = env.storage().persistent().get(&key).ok_or(Error::WorldNotFound)?;
+        extend_entry(&env, id, &key);
+
+        Ok(id)
+    }
+
+    /// Get the admin of the world
+    pub fn get_admin(env: Env, id: WorldId) -> Result<Address, Error> {
+        Self::load_admin(&env, id)
+    }
+
+    /// Replace the admin, authorised by the current admin
+    pub fn set_admin(env: Env, id: WorldId, new_admin: Address) -> Result<(), Error> {
+        Self::require_admin(&env, id)?;
+        store_entry(&env, id, &DataKey::Admin(id), &new_admin);
+        env.events()
+            .publish((symbol_short!("world"), symbol_short!("admin"), id), new_admin);
+
+        Ok(())
+    }
+
+    /// Hand the world over to a new admin, authorised by both the current and the new admin so
+    /// ownership can't be passed to an address nobody controls
+    pub fn transfer_admin(env: Env, id: WorldId, new_admin: Address) -> Result<(), Error> {
+        Self::require_admin(&env, id)?;
+        new_admin.require_auth();
+        store_entry(&env, id, &DataKey::Admin(id), &new_admin);
+        env.events()
+            .publish((symbol_short!("world"), symbol_short!("admin"), id), new_admin);
+
+        Ok(())
+    }
+
+    /// Get the ttl configuration of the world
+    pub fn get_config(env: Env, id: WorldId) -> Result<WorldConfig, Error> {
+        Self::load_world(&env, id)?;
+        Ok(WorldConfig::load(&env, id))
+    }
+
+    /// Configure how long world entries are kept alive, admin only
+    pub fn set_config(env: Env, id: WorldId, config: WorldConfig) -> Result<(), Error> {
+        Self::require_admin(&env, id)?;
+        if config.ttl_threshold > config.ttl_extend_to
+            || config.ttl_extend_to > env.storage().max_ttl()
+        {
+            return Err(Error::InvalidConfig);
+        }
+        store_entry(&env, id, &DataKey::Config(id), &config);
+        env.events()
+            .publish((symbol_short!("world"), symbol_short!("config"), id), config);
+
+        Ok(())
+    }
+
+    /// Keep the given entities alive by extending the ttl of their entries, anyone can pay for it
+    pub fn extend_ttl(env: Env, id: WorldId, entities: Vec<Entity>) -> Result<(), Error> {
+        Self::load_world(&env, id)?;
+        for entity in entities.iter() {
+            let record = EntityRecord::load(&env, id, entity)?;
+            load_entry::<Change>(&env, id, &DataKey::Change(id, entity.index));
+            Slots::Archetype(id, record.bitmap).extend(&env, entity.index);
+            Slots::Owned(id, record.owner).extend(&env, entity.index);
+        }
+
+        Ok(())
+    }
+
+    /// Get the world, its counters and systems. Entities are read through `get_entity`,
+    /// `list_entities` and `query`
+    pub fn get_world(env: Env, id: WorldId) -> Result<World, Error> {
+        Self::load_world(&env, id)
+    }
+
+    /// Get everything the world holds for an entity
+    pub fn get_entity(env: Env, id: WorldId, entity: Entity) -> Result<EntityRecord, Error> {
+        Self::load_world(&env, id)?;
+        EntityRecord::load(&env, id, entity)
+    }
+
+    /// Get a summary of the world without loading its entities
+    pub fn world_info(env: Env, id: WorldId) -> Result<WorldInfo, Error> {
+        let world = Self::load_world(&env, id)?;
+
+        Ok(WorldInfo {
+            name: world.name,
+            entities: world.alive,
+            components: Register::count(&env, id),
+            systems: world.systems.len(),
+            clock: world.clock,
+        })
+    }
+
+    /// List the entities of the world from the `cursor` slot onwards, at most `limit` of them
+    pub fn list_entities(
+        env: Env,
+        id: WorldId,
+        cursor: Index,
+        limit: u32,
+    ) -> Result<EntityPage, Error> {
+        Ok(Self::load_world(&env, id)?.list_entities(&env, cursor, limit))
+    }
+
+    /// List the systems of the world from the `cursor` position onwards, at most `limit` of them
+    pub fn list_systems(
+        env: Env,
+        id: WorldId,
+        cursor: u32,
+        limit: u32,
+    ) -> Result<SystemPage, Error> {
+        Ok(Self::load_world(&env, id)?.list_systems(&env, cursor, limit))
+    }
+
+    /// List the registered components from the `cursor` bit onwards, at most `limit` of them
+    pub fn list_components(
+        env: Env,
+        id: WorldId,
+        cursor: u32,
+        limit: u32,
+    ) -> Result<ComponentPage, Error> {
+        if !Self::check_genesis(&env, id) {
+            return Err(Error::WorldNotFound);
+        }
+
+        Ok(Register::list(&env, id, cursor, limit))
+    }
+
+    /// Spawn an entity owned by `owner` in the world with a list of registered components,
+    /// returning the new entity
+    pub fn spawn(
+        env: Env,
+        id: WorldId,
+        owner: Address,
+        components: Vec<Address>,
+    ) -> Result<Entity, Error> {
+        owner.require_auth();
+        Self::update_world(&env, id, |world| {
+            Self::create(&env, &mut Batch::direct(), world, owner, components)
+        })
+    }
+
+    /// Despawn an entity in the world, owner only. Stale entities, whose generation no longer
+    /// matches their slot, are rejected
+    pub fn despawn(env: Env, id: WorldId, entity: Entity) -> Result<(), Error> {
+        Self::update_world(&env, id, |world| {
+            Self::require_owner(&env, id, entity)?;
+            Ok(((), Self::destroy(&env, &mut Batch::direct(), world, entity)?))
+        })
+    }
+
+    /// Hand an entity over to a new owner, authorised by the current owner
+    pub fn transfer_entity(
+        env: Env,
+        id: WorldId,
+        entity: Entity,
+        new_owner: Address,
+    ) -> Result<(), Error> {
+        let mut batch = Batch::direct();
+        let owner = Self::update_entity(&env, &mut batch, id, entity, |record, _| {
+            Ok(core::mem::replace(&mut record.owner, new_owner.clone()))
+        })?;
+        Self::remove_owned(&env, &mut batch, id, &owner, entity);
+        Self::add_owned(&env, &mut batch, id, &new_owner, entity);
+        env.events().publish(
+            (symbol_short!("entity"), symbol_short!("transfer"), id, owner),
+            (entity, new_owner),
+        );
+
+        Ok(())
+    }
+
+    /// Get the latest change to the component signature of an entity
+    pub fn get_change(env: Env, id: WorldId, entity: Entity) -> Result<Option<Change>, Error> {
+        Self::get_entity(env.clone(), id, entity)?;
+        Ok(load_entry(&env, id, &DataKey::Change(id, entity.index)))
+    }
+
+    /// Get the owner of an entity
+    pub fn owner_of(env: Env, id: WorldId, entity: Entity) -> Result<Address, Error> {
+        Ok(Self::get_entity(env, id, entity)?.owner)
+    }
+
+    /// List the entities owned by `owner` from the `cursor` slot onwards, at most `limit` of them
+    pub fn entities_of(
+        env: Env,
+        id: WorldId,
+        owner: Address,
+        cursor: Index,
+        limit: u32,
+    ) -> Result<EntityPage, Error> {
+        Self::load_world(&env, id)?;
+        let limit = limit.clamp(1, MAX_LIMIT);
+        let mut entities = Vec::new(&env);
+        let mut next = None;
+
+        for index in Slots::Owned(id, owner).iter(&env, cursor) {
+            if entities.len() == limit {
+                next = Some(index);
+                break;
+            }
+            let (entity, record) = World::member(&env, id, index);
+            entities.push_back((entity, record.components));
+        }
+
+        Ok(EntityPage { entities, next })
+    }
+
+    /// Add registered components to an existing entity, owner only, returning whether the entity
+    /// changed
+    pub fn insert_components(
+        env: Env,
+        id: WorldId,
+        entity: Entity,
+        components: Vec<Address>,
+    ) -> Result<bool, Error> {
+        Self::update_world(&env, id, |world| {
+            Self::require_owner(&env, id, entity)?;
+            Self::insert(&env, &mut Batch::direct(), world, entity, components)
+        })
+    }
+
+    /// Remove components from an existing entity, owner only, returning whether the entity
+    /// changed
+    pub fn remove_components(
+        env: Env,
+        id: WorldId,
+        entity: Entity,
+        components: Vec<Address>,
+    ) -> Result<bool, Error> {
+        Self::update_world(&env, id, |world| {
+            Self::require_owner(&env, id, entity)?;
+            Self::remove(&env, &mut Batch::direct(), world, entity, components)
+        })
+    }
+
+    /// Find the entities holding every component in `with` and none in `without` that pass
+    /// `filter`, from `QueryCursor::Start` or the cursor a previous page handed back onwards and
+    /// at most `limit` of them
+    pub fn query(
+        env: Env,
+        id: WorldId,
+        with: Bitmap,
+        without: Bitmap,
+        filter: Filter,
+        cursor: QueryCursor,
+        limit: u32,
+    ) -> Result<QueryPage, Error> {
+        Ok(Self::load_world(&env, id)?.query(&env, &with, &without, &filter, cursor, limit))
+    }
+
+    /// Get the value stored in a component of an entity, if one has been set
+    pub fn get_component(
+        env: Env,
+        id: WorldId,
+        entity: Entity,
+        component: Address,
+    ) -> Result<Option<Bytes>, Error> {
+        Self::get_entity(env, id, entity)?.get_component(component)
+    }
+
+    /// Set the value of a component the entity already has, owner only
+    pub fn set_component(
+        env: Env,
+        id: WorldId,
+        entity: Entity,
+        component: Address,
+        value: Bytes,
+    ) -> Result<(), Error> {
+        let now = Self::load_world(&env, id)?.clock;
+        Self::update_entity(&env, &mut Batch::direct(), id, entity, |record, batch| {
+            record.set_component(&env, batch, id, entity, component, value, now)
+        })
+    }
+
+    /// Run every system once in schedule order. Each system is handed the entities matching its
+    /// query and the commands it returns are applied to the world in order before the next system
+    /// runs. A batch failing part way through, hooks included, is rolled back whole and the tick
+    /// carries on
+    pub fn tick(env: Env, id: WorldId) -> Result<(), Error> {
+        let world = Self::load_world(&env, id)?;
+        let schedule = world.schedule(&env)?;
+
+        for system in schedule.iter() {
+            let info = world.systems.get_unchecked(system.clone());
+            // the changes a system made itself are stamped with its own run
+            let filter = match info.detect {
+                Detect::All => Filter::Any,
+                Detect::Added => Filter::AddedSince(info.last_run + 1),
+                Detect::Changed => Filter::ChangedSince(info.last_run + 1),
             };
-            env.storage().instance().set(&DataKey::World, &world);
+            let entities = Self::load_world(&env, id)?.members(&env, &info.query, &filter);
+            let commands = SystemClient::new(&env, &system).run(
+                &env.current_contract_address(),
+                &entities,
+                &load_resources(&env, id),
+            );
+
+            let last_run = Self::update_world(&env, id, |world| {
+                let mut world = Self::apply_batch(&env, world, &system, commands, &info.resources);
+                let info = SystemInfo {
+                    last_run: world.clock,
+                    ..info
+                };
+                world.systems.set(system.clone(), info);
+                world.clock += 1;
+                Ok((world.clock - 1, world))
+            })?;
+            env.events()
+                .publish((symbol_short!("system"), symbol_short!("run"), id, system), last_run);
+        }
+
+        Ok(())
+    }
+
+    /// Have `on_add` and `on_remove` called as `component` lands on and is taken off entities,
+    /// registering the component if it isn't already and returning its bit, admin only
+    pub fn set_hooks(
+        env: Env,
+        id: WorldId,
+        component: Address,
+        on_add: Option<Address>,
+        on_remove: Option<Address>,
+    ) -> Result<u32, Error> {
+        Self::require_admin(&env, id)?;
+        Ok(Register::set_hooks(&env, id, component, on_add, on_remove))
+    }
+
+    /// Register a component with the world so entities can hold it, returning its bit, admin
+    /// only. Components already registered keep their bit
+    pub fn register_component(env: Env, id: WorldId, component: Address) -> Result<u32, Error> {
+        Self::require_admin(&env, id)?;
+        Self::load_world(&env, id)?;
+        Ok(Register::register(&env, id, component))
+    }
+
+    /// Unregister a component from the world, admin only. Fails while any entity still holds
+    /// the component, as its bit would otherwise be left behind in their signatures
+    pub fn unregister_component(env: Env, id: WorldId, component: Address) -> Result<(), Error> {
+        Self::require_admin(&env, id)?;
+        let world = Self::load_world(&env, id)?;
+        if let Some(bit) = Register::lookup(&env, id, component.clone()) {
+            if world.archetypes.iter().any(|signature| signature.contains(bit)) {
+                return Err(Error::ComponentInUse);
+            }
         }
+        Register::unregister(&env, id, component)
     }
 
-    /// Get the world
-    pub fn get_world(env: Env) -> World {
-        env.storage()
-            .instance()
-            .get(&DataKey::World)
-            .expect("Seems we genesis has yet to happen :)")
+    /// Add a system running on the entities matching `query` to the world, admin only. The
+    /// system runs in `stage` ahead of the systems in `before` and behind those in `after`, fails
+    /// if that would leave the systems without an order to run in. `detect` narrows the entities
+    /// down to those changed since the system last ran
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_system(
+        env: Env,
+        id: WorldId,
+        system: Address,
+        query: Query,
+        stage: Symbol,
+        before: Vec<Address>,
+        after: Vec<Address>,
+        detect: Detect,
+    ) -> Result<(), Error> {
+        Self::require_admin(&env, id)?;
+        let info = SystemInfo {
+            query,
+            stage,
+            before,
+            after,
+            detect,
+            last_run: 0,
+            resources: Vec::new(&env),
+        };
+        Self::update_world(&env, id, |world| {
+            Ok(((), world.add_system(&env, system.clone(), info.clone())?))
+        })?;
+        env.events()
+            .publish((symbol_short!("system"), symbol_short!("add"), id, system), info);
+
+        Ok(())
+    }
+
+    /// Remove a system from the world, admin only
+    pub fn remove_system(env: Env, id: WorldId, system: Address) -> Result<(), Error> {
+        Self::require_admin(&env, id)?;
+        Self::update_world(&env, id, |world| Ok(((), world.remove_system(system.clone())?)))?;
+        env.events()
+            .publish((symbol_short!("system"), symbol_short!("remove"), id, system), ());
+
+        Ok(())
+    }
+
+    /// Replace the resources a system may write through its commands, admin only
+    pub fn grant_resources(
+        env: Env,
+        id: WorldId,
+        system: Address,
+        resources: Vec<Symbol>,
+    ) -> Result<(), Error> {
+        Self::require_admin(&env, id)?;
+        Self::update_world(&env, id, |mut world| {
+            let info = world.systems.get(system.clone()).ok_or(Error::SystemNotFound)?;
+            let resources = resources.clone();
+            world.systems.set(system.clone(), SystemInfo { resources, ..info });
+            Ok(((), world))
+        })?;
+        env.events()
+            .publish((symbol_short!("system"), symbol_short!("grant"), id, system), resources);
+
+        Ok(())
+    }
+
+    /// Get a resource of the world, if it is set
+    pub fn get_resource(env: Env, id: WorldId, key: Symbol) -> Result<Option<Bytes>, Error> {
+        Self::load_world(&env, id)?;
+        Ok(load_resources(&env, id).get(key))
+    }
+
+    /// Set a resource of the world, admin only
+    pub fn set_resource(env: Env, id: WorldId, key: Symbol, value: Bytes) -> Result<(), Error> {
+        Self::require_admin(&env, id)?;
+        write_resource(&env, &mut Batch::direct(), id, key, value);
+        Ok(())
+    }
+
+    /// Remove a resource of the world, admin only
+    pub fn remove_resource(env: Env, id: WorldId, key: Symbol) -> Result<(), Error> {
+        Self::require_admin(&env, id)?;
+        delete_resource(&env, &mut Batch::direct(), id, key)
+    }
+
+    /// Replace the stages systems run in, in the order they run, admin only. Fails if that would
+    /// leave the systems without an order to run in
+    pub fn set_stages(env: Env, id: WorldId, stages: Vec<Symbol>) -> Result<(), Error> {
+        Self::require_admin(&env, id)?;
+        Self::update_world(&env, id, |world| Ok(((), world.set_stages(&env, stages.clone())?)))?;
+        env.events()
+            .publish((symbol_short!("world"), symbol_short!("stages"), id), stages);
+
+        Ok(())
+    }
+
+    /// The order systems will run in on the next tick
+    pub fn schedule(env: Env, id: WorldId) -> Result<Vec<Address>, Error> {
+        Self::load_world(&env, id)?.schedule(&env)
+    }
+}
+
+impl Contract {
+    fn load_admin(env: &Env, id: WorldId) -> Result<Address, Error> {
+        let admin = load_entry(env, id, &DataKey::Admin(id)).ok_or(Error::WorldNotFound)?;
+        extend_instance(env, id);
+
+        Ok(admin)
+    }
+
+    fn require_admin(env: &Env, id: WorldId) -> Result<(), Error> {
+        Self::load_admin(env, id)?.require_auth();
+        Ok(())
+    }
+
+    fn add_owned(env: &Env, batch: &mut Batch, id: WorldId, owner: &Address, entity: Entity) {
+        Slots::Owned(id, owner.clone()).insert(env, batch, entity.index);
+    }
+
+    fn remove_owned(env: &Env, batch: &mut Batch, id: WorldId, owner: &Address, entity: Entity) {
+        Slots::Owned(id, owner.clone()).remove(env, batch, entity.index);
+    }
+
+    fn load_world(env: &Env, id: WorldId) -> Result<World, Error> {
+        let world = load_entry(env, id, &DataKey::World(id)).ok_or(Error::WorldNotFound)?;
+        extend_world(env, &world);
+        extend_instance(env, id);
+
+        Ok(world)
+    }
+
+    /// Load the world, apply `f` to it and store the world it hands back. Every world mutation
+    /// goes through here so none of them can forget to persist
+    fn update_world<T>(
+        env: &Env,
+        id: WorldId,
+        f: impl FnOnce(World) -> Result<(T, World), Error>,
+    ) -> Result<T, Error> {
+        let (result, world) = f(Self::load_world(env, id)?)?;
+        store_entry(env, id, &DataKey::World(id), &world);
+
+        Ok(result)
+    }
+
+    /// Load an entity, authorised by its owner, apply `f` to it and store it again. Entity
+    /// mutations go through here the same way world mutations go through `update_world`
+    fn update_entity<T>(
+        env: &Env,
+        batch: &mut Batch,
+        id: WorldId,
+        entity: Entity,
+        f: impl FnOnce(&mut EntityRecord, &mut Batch) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        Self::load_world(env, id)?;
+        Self::modify_entity(env, batch, id, entity, |record, batch| {
+            record.owner.require_auth();
+            f(record, batch)
+        })
+    }
+
+    /// Load an entity, apply `f` to it and store it again, without asking the owner. Only for
+    /// paths that are already authorised, like the owner checked entry points and systems
+    fn modify_entity<T>(
+        env: &Env,
+        batch: &mut Batch,
+        id: WorldId,
+        entity: Entity,
+        f: impl FnOnce(&mut EntityRecord, &mut Batch) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        Self::guard(env, id, entity)?;
+        let mut record = EntityRecord::load(env, id, entity)?;
+        let result = f(&mut record, batch)?;
+        record.store(env, batch, id, entity.index);
+
+        Ok(result)
+    }
+
+    fn require_owner(env: &Env, id: WorldId, entity: Entity) -> Result<(), Error> {
+        EntityRecord::load(env, id, entity)?.owner.require_auth();
+        Ok(())
+    }
+
+    fn create(
+        env: &Env,
+        batch: &mut Batch,
+        world: World,
+        owner: Address,
+        components: Vec<Address>,
+    ) -> Result<(Entity, World), Error> {
+        let id = world.id;
+        let (entity, world) = world.spawn::<Register>(env, batch, owner.clone(), components)?;
+        Self::add_owned(env, batch, id, &owner, entity);
+        let components = EntityRecord::load(env, id, entity)?.components;
+        let world = Self::notify(env, batch, world, Hook::OnAdd, entity, components)?;
+
+        Ok((entity, world))
     }
 
-    /// Spawn an entity in the world with a list of components
-    pub fn spawn(env: Env, components: Vec<Address>) {
-        if Self::check_genesis(&env) {
+    fn destroy(env: &Env, batch: &mut Batch, world: World, entity: Entity) -> Result<World, Error> {
+        let id = world.id;
+        Self::guard(env, id, entity)?;
+        let (record, world) = world.despawn(env, batch, entity)?;
+        Self::remove_owned(env, batch, id, &record.owner, entity);
 
-            let (updated, world) = env
-                .storage()
-                .instance()
-                .get::<_, World>(&DataKey::World)
-                .expect("what happened to my world!")
-                .spawn::<Register>(&env, components);
+        Self::notify(env, batch, world, Hook::OnRemove, entity, record.components)
+    }
+
+    fn insert(
+        env: &Env,
+        batch: &mut Batch,
+        mut world: World,
+        entity: Entity,
+        components: Vec<Address>,
+    ) -> Result<(bool, World), Error> {
+        let id = world.id;
+        let added = Self::modify_entity(env, batch, id, entity, |record, batch| {
+            let before = record.bitmap.clone();
+            let existing = record.components.clone();
+            let now = world.clock;
+            record.insert_components::<Register>(env, batch, id, entity, components, now)?;
+            world.move_archetype(env, batch, entity.index, &before, &record.bitmap);
+
+            let mut added = Vec::new(env);
+            for component in record.components.iter() {
+                if !existing.contains(&component) {
+                    added.push_back(component);
+                }
+            }
+            if !added.is_empty() {
+                batch.publish(
+                    env,
+                    (symbol_short!("entity"), symbol_short!("insert"), id),
+                    (entity, before, record.bitmap.clone()),
+                );
+            }
+            Ok(added)
+        })?;
+
+        let updated = !added.is_empty();
+        Ok((updated, Self::notify(env, batch, world, Hook::OnAdd, entity, added)?))
+    }
+
+    fn remove(
+        env: &Env,
+        batch: &mut Batch,
+        mut world: World,
+        entity: Entity,
+        components: Vec<Address>,
+    ) -> Result<(bool, World), Error> {
+        let id = world.id;
+        let removed = Self::modify_entity(env, batch, id, entity, |record, batch| {
+            let before = record.bitmap.clone();
+            let existing = record.components.clone();
+            record.remove_components::<Register>(env, batch, id, entity, components);
+            world.move_archetype(env, batch, entity.index, &before, &record.bitmap);
+
+            let mut removed = Vec::new(env);
+            for component in existing.iter() {
+                if !record.components.contains(&component) {
+                    removed.push_back(component);
+                }
+            }
+            if !removed.is_empty() {
+                batch.publish(
+                    env,
+                    (symbol_short!("entity"), symbol_short!("remove"), id),
+                    (entity, before, record.bitmap.clone()),
+                );
+            }
+            Ok(removed)
+        })?;
+
+        let updated = !removed.is_empty();
+        Ok((updated, Self::notify(env, batch, world, Hook::OnRemove, entity, removed)?))
+    }
+
+    /// Call the `hook` of each of the components for the entity and apply the commands handed
+    /// back. While they are applied the entity is guarded, so a hook can't set off hooks that
+    /// change the entity again mid-hook
+    fn notify(
+        env: &Env,
+        batch: &mut Batch,
+        mut world: World,
+        hook: Hook,
+        entity: Entity,
+        components: Vec<Address>,
+    ) -> Result<World, Error> {
+        for component in components.iter() {
+            let Some(contract) = Register::hook(env, world.id, component.clone(), hook) else {
+                continue;
+            };
+            let client = HookClient::new(env, &contract);
+            let commands = match hook {
+                Hook::OnAdd => client.on_add(&env.current_contract_address(), &entity, &component),
+                Hook::OnRemove => {
+                    client.on_remove(&env.current_contract_address(), &entity, &component)
+                }
+            };
+
+            let key = DataKey::Hooked(world.id, entity.index);
+            env.storage().temporary().set(&key, &true);
+            let applied = commands.iter().try_fold(world, |world, command| {
+                Self::apply(env, batch, world, command, &Vec::new(env))
+            });
+            env.storage().temporary().remove(&key);
+            world = applied?;
+        }
+
+        Ok(world)
+    }
+
+    /// Refuse to change an entity whose hooks are running
+    fn guard(env: &Env, id: WorldId, entity: Entity) -> Result<(), Error> {
+        if env.storage().temporary().has(&DataKey::Hooked(id, entity.index)) {
+            return Err(Error::Reentrant);
+        }
+        Ok(())
+    }
+
+    /// Apply a batch of commands handed back by `system`, allowed to write `resources`. A batch
+    /// failing part way through, hooks included, is put back as it was, in which case
+    /// `("system", "dropped", id, system)` is published with the error and the world is handed
+    /// back untouched
+    fn apply_batch(
+        env: &Env,
+        world: World,
+        system: &Address,
+        commands: Vec<Command>,
+        resources: &Vec<Symbol>,
+    ) -> World {
+        let id = world.id;
+        let mut batch = Batch::begin();
+        let applied = commands.iter().try_fold(world.clone(), |world, command| {
+            Self::apply(env, &mut batch, world, command, resources)
+        });
+
+        match applied {
+            Ok(world) => {
+                batch.commit(env);
+                world
+            }
+            Err(error) => {
+                batch.rollback(env);
+                env.events().publish(
+                    (symbol_short!("system"), symbol_short!("dropped"), id, system.clone()),
+                    error as u32,
+                );
+                world
+            }
+        }
+    }
 
-            if updated {
-                env.storage().instance().set(&DataKey::World, &world);
+    /// Apply a command handed back by a system or hook allowed to write `resources`
+    fn apply(
+        env: &Env,
+        batch: &mut Batch,
+        world: World,
+        command: Command,
+        resources: &Vec<Symbol>,
+    ) -> Result<World, Error> {
+        match command {
+            Command::Spawn(owner, components) => {
+                Ok(Self::create(env, batch, world, owner, components)?.1)
+            }
+            Command::Despawn(entity) => Self::destroy(env, batch, world, entity),
+            Command::Insert(entity, components) => {
+                Ok(Self::insert(env, batch, world, entity, components)?.1)
+            }
+            Command::Remove(entity, components) => {
+                Ok(Self::remove(env, batch, world, entity, components)?.1)
+            }
+            Command::SetComponent(entity, component, value) => {
+                let now = world.clock;
+                Self::modify_entity(env, batch, world.id, entity, |record, batch| {
+                    record.set_component(env, batch, world.id, entity, component, value, now)
+                })?;
+                Ok(world)
+            }
+            Command::SetResource(key, value) => {
+                if !resources.contains(&key) {
+                    return Err(Error::Unauthorized);
+                }
+                write_resource(env, batch, world.id, key, value);
+                Ok(world)
+            }
+            Command::RemoveResource(key) => {
+                if !resources.contains(&key) {
+                    return Err(Error::Unauthorized);
+                }
+                delete_resource(env, batch, world.id, key)?;
+                Ok(world)
             }
         }
     }
+}
+impl WorldConfig {
+    /// Read the configuration of the world `id`, which keeps its own entry alive with itself
+    /// rather than through `load_entry`
+    fn load(env: &Env, id: WorldId) -> Self {
+        let key = DataKey::Config(id);
+        let Some(config) = env.storage().persistent().get::<_, WorldConfig>(&key) else {
+            return WorldConfig {
+                ttl_threshold: 6 * DAY_IN_LEDGERS,
+                ttl_extend_to: 7 * DAY_IN_LEDGERS,
+            };
+        };
+        env.storage()
+            .persistent()
+            .extend_ttl(&key, config.ttl_threshold, config.ttl_extend_to);
+
+        config
+    }
+}
+
+/// Extend the contract instance, which only holds the world counter next to the contract code, as
+/// configured by the world `id`
+fn extend_instance(env: &Env, id: WorldId) {
+    let config = WorldConfig::load(env, id);
+    env.storage()
+        .instance()
+        .extend_ttl(config.ttl_threshold, config.ttl_extend_to);
+}
+
+/// Keep the entries every world has next to its `World` entry alive along with it, the config
+/// keeps itself alive as it is read
+fn extend_world(env: &Env, world: &World) {
+    let config = WorldConfig::load(env, world.id);
+    let storage = env.storage().persistent();
+    for key in [
+        DataKey::Name(world.name.clone()),
+        DataKey::Admin(world.id),
+        DataKey::Register(world.id),
+        DataKey::Resources(world.id),
+    ] {
+        if storage.has(&key) {
+            storage.extend_ttl(&key, config.ttl_threshold, config.ttl_extend_to);
+        }
+    }
+}
+
+/// The resources of the world, they live in an entry of their own next to the world
+fn load_resources(env: &Env, id: WorldId) -> Map<Symbol, Bytes> {
+    let resources = load_entry(env, id, &DataKey::Resources(id));
+    resources.unwrap_or_else(|| Map::new(env))
+}
+
+fn write_resource(env: &Env, batch: &mut Batch, id: WorldId, key: Symbol, value: Bytes) {
+    let mut resources = load_resources(env, id);
+    resources.set(key.clone(), value.clone());
+    batch.store(env, id, &DataKey::Resources(id), &resources);
+    batch.publish(env, (symbol_short!("resource"), symbol_short!("set"), id, key), value);
+}
+
+fn delete_resource(env: &Env, batch: &mut Batch, id: WorldId, key: Symbol) -> Result<(), Error> {
+    let mut resources = load_resources(env, id);
+    resources.remove(key.clone()).ok_or(Error::ResourceNotFound)?;
+    batch.store(env, id, &DataKey::Resources(id), &resources);
+    batch.publish(env, (symbol_short!("resource"), symbol_short!("remove"), id, key), ());
+    Ok(())
+}
+
+/// Read a persistent entry of the world `id`, extending its ttl if it exists
+fn load_entry<V: TryFromVal<Env, Val>>(env: &Env, id: WorldId, key: &DataKey) -> Option<V> {
+    let value = env.storage().persistent().get(key)?;
+    extend_entry(env, id, key);
+    Some(value)
+}
+
+/// Write a persistent entry of the world `id` and extend its ttl
+fn store_entry<V: IntoVal<Env, Val>>(env: &Env, id: WorldId, key: &DataKey, value: &V) {
+    env.storage().persistent().set(key, value);
+    extend_entry(env, id, key);
+}
+
+/// Remove a persistent entry of the world `id`
+fn remove_entry(env: &Env, key: &DataKey) {
+    env.storage().persistent().remove(key);
+}
+
+fn extend_entry(env: &Env, id: WorldId, key: &DataKey) {
+    let config = WorldConfig::load(env, id);
+    env.storage()
+        .persistent()
+        .extend_ttl(key, config.ttl_threshold, config.ttl_extend_to);
+}
+
+/// The entries a batch of commands writes and the events it publishes, kept in memory while the
+/// batch is applied so a batch failing part way through can be put back as it was. Only an active
+/// batch journals, the entry points changing a single thing write and publish straight away
+struct Batch {
+    journal: Option<alloc::vec::Vec<(DataKey, Option<Val>)>>,
+    events: alloc::vec::Vec<(Vec<Val>, Val)>,
+}
+
+impl Batch {
+    /// A batch journaling everything it writes until it is committed or rolled back
+    fn begin() -> Self {
+        Batch {
+            journal: Some(alloc::vec::Vec::new()),
+            events: alloc::vec::Vec::new(),
+        }
+    }
 
-    /// Despawn an entity in the world
-    pub fn despawn(env: Env, component: Address) {
-        if Self::check_genesis(&env) {
-            env.storage()
-                .instance()
-                .get::<_, World>(&DataKey::World)
-                .expect("what happened to my world!")
-                .despawn::<Register>(&env, component);
+    /// Writes and events going straight through
+    fn direct() -> Self {
+        Batch {
+            journal: None,
+            events: alloc::vec::Vec::new(),
         }
     }
 
-    /// Add system to world
-    pub fn add_system(env: Env, query: Query, system: Address) {
-        if Self::check_genesis(&env) {
-            env.storage()
-                .instance()
-                .get::<_, World>(&DataKey::World)
-                .expect("what happened to my world!")
-                .add_system(query, system);
+    /// Note what an entry held before the batch writes it
+    fn journal(&mut self, env: &Env, key: &DataKey) {
+        if let Some(journal) = &mut self.journal {
+            journal.push((key.clone(), env.storage().persistent().get(key)));
         }
     }
 
-    pub fn remove_system(env: Env, query: Query) {
-        if Self::check_genesis(&env) {
-            env.storage()
-                .instance()
-                .get::<_, World>(&DataKey::World)
-                .expect("what happened to my world!")
-                .remove_system(query);
+    /// Write a persistent entry of the world `id` and extend its ttl
+    fn store<V: IntoVal<Env, Val>>(&mut self, env: &Env, id: WorldId, key: &DataKey, value: &V) {
+        self.journal(env, key);
+        store_entry(env, id, key, value);
+    }
+
+    /// Remove a persistent entry
+    fn remove(&mut self, env: &Env, key: &DataKey) {
+        self.journal(env, key);
+        remove_entry(env, key);
+    }
+
+    /// Publish an event, held back until an active batch commits
+    fn publish<T: Topics, D: IntoVal<Env, Val>>(&mut self, env: &Env, topics: T, data: D) {
+        if self.journal.is_some() {
+            self.events.push((topics.into_val(env), data.into_val(env)));
+        } else {
+            env.events().publish(topics, data);
+        }
+    }
+
+    /// Keep what the batch wrote and publish the events it held back
+    fn commit(self, env: &Env) {
+        for (topics, data) in self.events {
+            env.events().publish(HeldTopics(topics), data);
+        }
+    }
+
+    /// Put every entry the batch wrote back as it was, latest write first, and forget the events
+    /// it held back
+    fn rollback(self, env: &Env) {
+        for (key, before) in self.journal.into_iter().flatten().rev() {
+            match before {
+                Some(before) => env.storage().persistent().set(&key, &before),
+                None => env.storage().persistent().remove(&key),
+            }
         }
     }
 }
-#[test]
-fn hello() {
-    let env = Env::default();
-    let contract_id = env.register_contract(None, Contract);
-    let client = ContractClient::new(&env, &contract_id);
 
-    // let words = client.hello(&symbol_short!("Dev"));
-    // assert_eq!(
-    //     words,
-    //     vec![&env, symbol_short!("Hello"), symbol_short!("Dev"),]
-    // );
+/// The topics of an event a batch held back, published as they are once the batch commits
+struct HeldTopics(Vec<Val>);
+
+impl TryFromVal<Env, HeldTopics> for Vec<Val> {
+    type Error = ConversionError;
+
+    fn try_from_val(_env: &Env, topics: &HeldTopics) -> Result<Self, Self::Error> {
+        Ok(topics.0.clone())
+    }
 }
+
+impl Topics for HeldTopics {}
+
+#[cfg(test)]
+mod test;
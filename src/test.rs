@@ -0,0 +1,1572 @@
+#![cfg(test)]
+extern crate std;
+
+use super::*;
+use soroban_sdk::{
+    symbol_short,
+    testutils::{Address as _, AuthorizedFunction, AuthorizedInvocation, Events, Ledger},
+    vec, xdr, IntoVal,
+};
+
+fn setup(env: &Env) -> (ContractClient<'_>, u32) {
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(env, &contract_id);
+    let world = client.genesis(&Address::generate(env), &symbol_short!("Dev"));
+    (client, world)
+}
+
+/// Add a system to the default stage without any ordering constraints
+fn add_system(env: &Env, client: &ContractClient, world: u32, system: &Address, query: &Query) {
+    let none = Vec::new(env);
+    client.add_system(&world, system, query, &symbol_short!("update"), &none, &none, &Detect::All);
+}
+
+/// Every entity owned by `owner`, read back a page at a time
+fn owned(env: &Env, client: &ContractClient, world: u32, owner: &Address) -> Vec<Entity> {
+    let mut entities = Vec::new(env);
+    let mut cursor = Some(0);
+    while let Some(slot) = cursor {
+        let page = client.entities_of(&world, owner, &slot, &1);
+        for (entity, _) in page.entities.iter() {
+            entities.push_back(entity);
+        }
+        cursor = page.next;
+    }
+    entities
+}
+
+/// A fresh component registered with the world
+fn component(env: &Env, client: &ContractClient, world: u32) -> Address {
+    let component = Address::generate(env);
+    client.register_component(&world, &component);
+    component
+}
+
+#[test]
+fn hello() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+
+    assert_eq!(client.get_world(&world).name, symbol_short!("Dev"));
+}
+
+#[test]
+fn despawn_removes_entity() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let owner = Address::generate(&env);
+
+    let entity = client.spawn(&world, &owner, &vec![&env, component(&env, &client, world)]);
+    assert_eq!(client.get_entity(&world, &entity).owner, owner);
+
+    client.despawn(&world, &entity);
+    assert_eq!(client.try_get_entity(&world, &entity), Err(Ok(Error::EntityNotFound)));
+    assert_eq!(client.try_despawn(&world, &entity), Err(Ok(Error::EntityNotFound)));
+}
+
+#[test]
+fn despawned_slots_are_recycled_with_new_generation() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let owner = Address::generate(&env);
+    let component = component(&env, &client, world);
+
+    let first = client.spawn(&world, &owner, &vec![&env, component.clone()]);
+    client.despawn(&world, &first);
+
+    let second = client.spawn(&world, &owner, &vec![&env, component]);
+    assert_eq!(second.index, first.index);
+    assert_eq!(second.generation, first.generation + 1);
+
+    // the stale handle must not despawn the entity now living in its slot
+    assert_eq!(client.try_despawn(&world, &first), Err(Ok(Error::EntityNotFound)));
+    assert_eq!(client.get_entity(&world, &second).generation, second.generation);
+    client.despawn(&world, &second);
+}
+
+#[test]
+fn insert_and_remove_components() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let owner = Address::generate(&env);
+    let position = component(&env, &client, world);
+    let velocity = component(&env, &client, world);
+
+    let entity = client.spawn(&world, &owner, &vec![&env, position.clone()]);
+
+    let components = vec![&env, velocity.clone(), position.clone()];
+    assert!(client.insert_components(&world, &entity, &components));
+    let record = client.get_entity(&world, &entity);
+    assert_eq!(record.components, vec![&env, position.clone(), velocity.clone()]);
+    assert_eq!(client.get_change(&world, &entity).unwrap().entity, entity);
+
+    // nothing new to add
+    assert!(!client.insert_components(&world, &entity, &vec![&env, velocity.clone()]));
+
+    assert!(client.remove_components(&world, &entity, &vec![&env, position.clone()]));
+    assert_eq!(client.get_entity(&world, &entity).components, vec![&env, velocity.clone()]);
+
+    // stale entities are rejected
+    client.despawn(&world, &entity);
+    assert_eq!(
+        client.try_insert_components(&world, &entity, &vec![&env, position]),
+        Err(Ok(Error::EntityNotFound))
+    );
+    assert_eq!(
+        client.try_remove_components(&world, &entity, &vec![&env, velocity]),
+        Err(Ok(Error::EntityNotFound))
+    );
+}
+
+#[test]
+fn component_values() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let owner = Address::generate(&env);
+    let position = component(&env, &client, world);
+    let velocity = component(&env, &client, world);
+
+    let entity = client.spawn(&world, &owner, &vec![&env, position.clone()]);
+    assert_eq!(client.get_component(&world, &entity, &position), None);
+
+    let value = Bytes::from_array(&env, &[1, 2]);
+    client.set_component(&world, &entity, &position, &value);
+    assert_eq!(client.get_component(&world, &entity, &position), Some(value.clone()));
+
+    // only components on the entity can hold a value
+    assert_eq!(
+        client.try_set_component(&world, &entity, &velocity, &value),
+        Err(Ok(Error::MissingComponent))
+    );
+
+    client.remove_components(&world, &entity, &vec![&env, position.clone()]);
+    assert_eq!(
+        client.try_get_component(&world, &entity, &position),
+        Err(Ok(Error::MissingComponent))
+    );
+
+    client.insert_components(&world, &entity, &vec![&env, position.clone()]);
+    assert_eq!(client.get_component(&world, &entity, &position), None);
+    client.set_component(&world, &entity, &position, &value);
+    client.despawn(&world, &entity);
+    assert_eq!(
+        client.try_get_component(&world, &entity, &position),
+        Err(Ok(Error::EntityNotFound))
+    );
+    assert_eq!(
+        client.try_set_component(&world, &entity, &position, &value),
+        Err(Ok(Error::EntityNotFound))
+    );
+}
+
+#[test]
+fn bitmap_spans_multiple_words() {
+    let env = Env::default();
+
+    let mut bitmap = Bitmap::from_bit(&env, 1);
+    bitmap.set(127);
+    bitmap.set(200);
+    assert!(bitmap.contains(1));
+    assert!(bitmap.contains(127));
+    assert!(bitmap.contains(200));
+    assert!(!bitmap.contains(128));
+
+    let high = Bitmap::from_bit(&env, 200);
+    assert!(bitmap.contains_all(&high));
+    assert!(!high.contains_all(&bitmap));
+    assert!(bitmap.intersects(&high));
+    assert!(!high.intersects(&Bitmap::from_bit(&env, 1)));
+
+    let union = Bitmap::from_bit(&env, 1).union(&high);
+    assert!(union.contains(1) && union.contains(200));
+    assert!(bitmap.contains_all(&union));
+
+    // clearing the only bit of the top word trims it so equal sets compare equal
+    bitmap.clear(200);
+    let mut expected = Bitmap::from_bit(&env, 1);
+    expected.set(127);
+    assert_eq!(bitmap, expected);
+    bitmap.clear(1);
+    bitmap.clear(127);
+    assert!(bitmap.is_empty());
+}
+
+#[test]
+fn register_is_persisted_and_idempotent() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let admin = client.get_admin(&world);
+    let owner = Address::generate(&env);
+    let position = Address::generate(&env);
+    let velocity = Address::generate(&env);
+
+    // only the admin registers components, and only registered components can be held
+    assert_eq!(client.register_component(&world, &position), 0);
+    assert_eq!(env.auths()[0].0, admin);
+    let first = client.spawn(&world, &owner, &vec![&env, position.clone()]);
+    assert_eq!(
+        client.try_insert_components(&world, &first, &vec![&env, velocity.clone()]),
+        Err(Ok(Error::ComponentNotRegistered))
+    );
+    assert_eq!(client.register_component(&world, &velocity), 1);
+    assert_eq!(client.register_component(&world, &position), 0);
+    let second = client.spawn(&world, &owner, &vec![&env, velocity.clone(), position.clone()]);
+
+    let first_bitmap = client.get_entity(&world, &first).bitmap;
+    let second_bitmap = client.get_entity(&world, &second).bitmap;
+    assert_eq!(first_bitmap, Bitmap::from_bit(&env, 0));
+    assert!(second_bitmap.contains(0) && second_bitmap.contains(1));
+
+    // removing a component clears its bit again
+    assert!(client.remove_components(&world, &second, &vec![&env, position]));
+    assert_eq!(client.get_entity(&world, &second).bitmap, Bitmap::from_bit(&env, 1));
+}
+
+#[test]
+fn more_than_127_components() {
+    let env = Env::default();
+    env.budget().reset_unlimited();
+    let (client, world) = setup(&env);
+    let owner = Address::generate(&env);
+
+    let mut components = Vec::new(&env);
+    for _ in 0..130 {
+        components.push_back(component(&env, &client, world));
+    }
+    let entity = client.spawn(&world, &owner, &components);
+
+    let bitmap = client.get_entity(&world, &entity).bitmap;
+    assert!(bitmap.contains(0));
+    assert!(bitmap.contains(127));
+    assert!(bitmap.contains(129));
+    assert!(!bitmap.contains(130));
+}
+
+#[test]
+fn systems_are_persisted() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let system = Address::generate(&env);
+    let query = Bitmap::from_bit(&env, 0);
+
+    add_system(&env, &client, world, &system, &query);
+    assert_eq!(
+        client.get_world(&world).systems.get(system.clone()),
+        Some(SystemInfo {
+            query: query.clone(),
+            stage: symbol_short!("update"),
+            before: Vec::new(&env),
+            after: Vec::new(&env),
+            detect: Detect::All,
+            last_run: 0,
+            resources: Vec::new(&env),
+        })
+    );
+
+    // several systems may share a query, but each is only added once
+    let other = Address::generate(&env);
+    add_system(&env, &client, world, &other, &query);
+    assert_eq!(client.get_world(&world).systems.len(), 2);
+    let (stage, none) = (symbol_short!("update"), Vec::new(&env));
+    assert_eq!(
+        client.try_add_system(&world, &system, &query, &stage, &none, &none, &Detect::All),
+        Err(Ok(Error::SystemConflict))
+    );
+
+    client.remove_system(&world, &system);
+    client.remove_system(&world, &other);
+    assert!(client.get_world(&world).systems.is_empty());
+    assert_eq!(client.try_remove_system(&world, &system), Err(Ok(Error::SystemNotFound)));
+}
+
+#[test]
+fn entity_mutations_are_persisted() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let owner = Address::generate(&env);
+    let position = component(&env, &client, world);
+
+    let entity = client.spawn(&world, &owner, &vec![&env, position.clone()]);
+    assert_eq!(client.get_world(&world).counter, 1);
+    assert_eq!(client.get_world(&world).alive, 1);
+
+    client.despawn(&world, &entity);
+    assert_eq!(client.get_world(&world).alive, 0);
+    assert_eq!(client.get_world(&world).free, entity.index);
+    env.as_contract(&client.address, || {
+        let key = DataKey::Free(world, entity.index);
+        let slot: FreeSlot = env.storage().persistent().get(&key).unwrap();
+        assert_eq!(slot, FreeSlot {
+            generation: entity.generation + 1,
+            next: 0,
+        });
+    });
+
+    // the slot is handed out again with its generation bumped
+    let recycled = client.spawn(&world, &owner, &vec![&env, position]);
+    assert_eq!(recycled, Entity {
+        index: entity.index,
+        generation: entity.generation + 1,
+    });
+    assert_eq!(client.get_world(&world).free, 0);
+    assert_eq!(client.get_world(&world).counter, 1);
+}
+
+#[test]
+fn errors_before_and_after_genesis() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+    let admin = Address::generate(&env);
+    let owner = Address::generate(&env);
+    let position = Address::generate(&env);
+    env.mock_all_auths();
+
+    assert_eq!(client.try_get_world(&0), Err(Ok(Error::WorldNotFound)));
+    assert_eq!(client.try_get_admin(&0), Err(Ok(Error::WorldNotFound)));
+    assert_eq!(client.try_list_components(&0, &0, &10), Err(Ok(Error::WorldNotFound)));
+    assert_eq!(
+        client.try_spawn(&0, &owner, &vec![&env, position.clone()]),
+        Err(Ok(Error::WorldNotFound))
+    );
+
+    let world = client.genesis(&admin, &symbol_short!("Dev"));
+    assert_eq!(
+        client.try_genesis(&admin, &symbol_short!("Dev")),
+        Err(Ok(Error::AlreadyInitialized))
+    );
+    assert_eq!(client.try_spawn(&world, &owner, &vec![&env]), Err(Ok(Error::NoComponents)));
+    assert_eq!(
+        client.try_unregister_component(&world, &position),
+        Err(Ok(Error::ComponentNotRegistered))
+    );
+    assert_eq!(
+        client.try_spawn(&world, &owner, &vec![&env, position.clone()]),
+        Err(Ok(Error::ComponentNotRegistered))
+    );
+
+    client.register_component(&world, &position);
+    let entity = client.spawn(&world, &owner, &vec![&env, position.clone()]);
+
+    // components still held by an entity can't be unregistered
+    assert_eq!(
+        client.try_unregister_component(&world, &position),
+        Err(Ok(Error::ComponentInUse))
+    );
+    client.despawn(&world, &entity);
+    client.unregister_component(&world, &position);
+    assert_eq!(client.list_components(&world, &0, &10).components, Vec::new(&env));
+}
+
+#[test]
+fn worlds_are_independent() {
+    let env = Env::default();
+    let (client, first) = setup(&env);
+    let admin = Address::generate(&env);
+    let owner = Address::generate(&env);
+    let position = Address::generate(&env);
+    let velocity = Address::generate(&env);
+
+    let second = client.genesis(&admin, &symbol_short!("Match"));
+    assert_ne!(second, first);
+    assert_eq!(client.world_id(&symbol_short!("Match")), second);
+    assert_eq!(client.get_world(&second).name, symbol_short!("Match"));
+    assert_eq!(client.get_admin(&second), admin);
+    assert_ne!(client.get_admin(&first), admin);
+    assert_eq!(
+        client.try_genesis(&admin, &symbol_short!("Dev")),
+        Err(Ok(Error::AlreadyInitialized))
+    );
+
+    // each world has its own entities, register and systems
+    client.register_component(&first, &position);
+    client.register_component(&second, &velocity);
+    client.register_component(&second, &position);
+    let one = client.spawn(&first, &owner, &vec![&env, position.clone()]);
+    let two = client.spawn(&second, &owner, &vec![&env, velocity.clone(), position.clone()]);
+    assert_eq!(one.index, two.index);
+    assert_eq!(client.get_entity(&first, &one).components, vec![&env, position.clone()]);
+    let registered = client.list_components(&second, &0, &10).components;
+    assert_eq!(registered, vec![&env, (0, velocity), (1, position)]);
+    assert_eq!(owned(&env, &client, second, &owner), vec![&env, two]);
+    add_system(&env, &client, second, &Address::generate(&env), &Bitmap::from_bit(&env, 0));
+    assert_eq!(client.world_info(&first).systems, 0);
+    assert_eq!(client.world_info(&second).systems, 1);
+
+    client.despawn(&first, &one);
+    assert_eq!(client.get_entity(&second, &two).owner, owner);
+    assert_eq!(client.try_get_world(&2), Err(Ok(Error::WorldNotFound)));
+    assert_eq!(client.try_world_id(&symbol_short!("None")), Err(Ok(Error::WorldNotFound)));
+
+    // the instance only counts the worlds, each world lives in entries of its own
+    env.as_contract(&client.address, || {
+        assert!(!env.storage().instance().has(&DataKey::World(first)));
+        assert!(env.storage().persistent().has(&DataKey::World(second)));
+        assert!(env.storage().persistent().has(&DataKey::Admin(second)));
+    });
+}
+
+#[test]
+fn admin_authorizes_world_configuration() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let admin = client.get_admin(&world);
+    let system = Address::generate(&env);
+    let query = Bitmap::from_bit(&env, 0);
+    let stage = symbol_short!("update");
+    let none: Vec<Address> = Vec::new(&env);
+
+    client.add_system(&world, &system, &query, &stage, &none, &none, &Detect::All);
+    assert_eq!(
+        env.auths(),
+        std::vec![(
+            admin.clone(),
+            AuthorizedInvocation {
+                function: AuthorizedFunction::Contract((
+                    client.address.clone(),
+                    Symbol::new(&env, "add_system"),
+                    (world, system.clone(), query.clone(), stage, none.clone(), none, Detect::All)
+                        .into_val(&env),
+                )),
+                sub_invocations: std::vec![],
+            }
+        )]
+    );
+
+    client.remove_system(&world, &system);
+    assert_eq!(env.auths()[0].0, admin);
+
+    // a plain transfer needs the new admin to sign as well
+    let next = Address::generate(&env);
+    client.transfer_admin(&world, &next);
+    let signers: std::vec::Vec<Address> = env.auths().into_iter().map(|(a, _)| a).collect();
+    assert_eq!(signers, std::vec![admin.clone(), next.clone()]);
+    assert_eq!(client.get_admin(&world), next);
+
+    client.set_admin(&world, &admin);
+    assert_eq!(env.auths()[0].0, next);
+    assert_eq!(client.get_admin(&world), admin);
+}
+
+#[test]
+fn entities_are_owned() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    let position = component(&env, &client, world);
+
+    let first = client.spawn(&world, &alice, &vec![&env, position.clone()]);
+    assert_eq!(env.auths()[0].0, alice);
+    let second = client.spawn(&world, &alice, &vec![&env, position.clone()]);
+    assert_eq!(client.owner_of(&world, &first), alice);
+    assert_eq!(owned(&env, &client, world, &alice), vec![&env, first, second]);
+
+    client.set_component(&world, &first, &position, &Bytes::from_array(&env, &[1]));
+    assert_eq!(env.auths()[0].0, alice);
+
+    client.transfer_entity(&world, &first, &bob);
+    assert_eq!(env.auths()[0].0, alice);
+    assert_eq!(client.owner_of(&world, &first), bob);
+    assert_eq!(owned(&env, &client, world, &alice), vec![&env, second]);
+    assert_eq!(owned(&env, &client, world, &bob), vec![&env, first]);
+
+    // only the new owner can now mutate or despawn the entity
+    client.remove_components(&world, &first, &vec![&env, position.clone()]);
+    assert_eq!(env.auths()[0].0, bob);
+    client.despawn(&world, &first);
+    assert_eq!(env.auths()[0].0, bob);
+    assert!(owned(&env, &client, world, &bob).is_empty());
+    assert_eq!(client.try_owner_of(&world, &first), Err(Ok(Error::EntityNotFound)));
+
+    // owners are listed a page at a time in slot order, the recycled slot comes first
+    let third = client.spawn(&world, &alice, &vec![&env, position.clone()]);
+    let page = client.entities_of(&world, &alice, &0, &1);
+    assert_eq!(page.entities, vec![&env, (third, vec![&env, position.clone()])]);
+    assert_eq!(page.next, Some(second.index));
+    assert_eq!(
+        client.try_entities_of(&(world + 1), &alice, &0, &1),
+        Err(Ok(Error::WorldNotFound))
+    );
+
+    // each chunk of slots lives in an entry of its own and goes once it is empty
+    env.as_contract(&client.address, || {
+        let slots = Slots::Owned(world, bob.clone());
+        let batch = &mut Batch::direct();
+        for index in [3, 130, 300] {
+            slots.insert(&env, batch, index);
+        }
+        assert!(!slots.remove(&env, batch, 130));
+        let key = DataKey::OwnedChunk(world, bob.clone(), 1);
+        assert!(!env.storage().persistent().has(&key));
+        assert_eq!(slots.iter(&env, 4).collect::<std::vec::Vec<_>>(), [300]);
+        assert_eq!(slots.iter(&env, 0).collect::<std::vec::Vec<_>>(), [3, 300]);
+    });
+}
+
+#[test]
+fn query_with_and_without() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let owner = Address::generate(&env);
+    let position = component(&env, &client, world);
+    let velocity = component(&env, &client, world);
+    let frozen = component(&env, &client, world);
+
+    let still = client.spawn(&world, &owner, &vec![&env, position.clone()]);
+    let moving = client.spawn(&world, &owner, &vec![&env, position.clone(), velocity.clone()]);
+    let components = vec![&env, position.clone(), velocity.clone(), frozen];
+    let stuck = client.spawn(&world, &owner, &components);
+
+    let with_position = Bitmap::from_bit(&env, 0);
+    let (start, none) = (QueryCursor::Start, Bitmap::new(&env));
+    let page = client.query(&world, &with_position, &none, &Filter::Any, &start, &10);
+    assert_eq!(page.entities.len(), 3);
+    assert_eq!(page.entities.get_unchecked(0), (still, vec![&env, position.clone()]));
+    assert_eq!(page.next, QueryCursor::Done);
+
+    let mut with_velocity = with_position.clone();
+    with_velocity.set(1);
+    let without = Bitmap::from_bit(&env, 2);
+    let page = client.query(&world, &with_velocity, &without, &Filter::Any, &start, &10);
+    assert_eq!(
+        page.entities,
+        vec![&env, (moving, vec![&env, position.clone(), velocity])]
+    );
+
+    // paging through the results a single entity at a time
+    let page = client.query(&world, &with_position, &none, &Filter::Any, &start, &1);
+    assert_eq!(page.entities.get_unchecked(0).0, still);
+    let mut both = with_position.clone();
+    both.set(1);
+    assert_eq!(page.next, QueryCursor::At(both, moving.index));
+    let page = client.query(&world, &with_position, &none, &Filter::Any, &page.next, &2);
+    assert_eq!(page.entities.get_unchecked(1).0, stuck);
+    assert_eq!(page.next, QueryCursor::Done);
+}
+
+#[test]
+fn paginated_reads() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let owner = Address::generate(&env);
+    let position = component(&env, &client, world);
+    let velocity = component(&env, &client, world);
+    let movement = Address::generate(&env);
+    let render = Address::generate(&env);
+
+    let first = client.spawn(&world, &owner, &vec![&env, position.clone()]);
+    let second = client.spawn(&world, &owner, &vec![&env, position.clone(), velocity.clone()]);
+    add_system(&env, &client, world, &render, &Bitmap::from_bit(&env, 0));
+    add_system(&env, &client, world, &movement, &Bitmap::from_bit(&env, 1));
+
+    assert_eq!(
+        client.world_info(&world),
+        WorldInfo {
+            name: symbol_short!("Dev"),
+            entities: 2,
+            components: 2,
+            systems: 2,
+            clock: 1,
+        }
+    );
+
+    let page = client.list_entities(&world, &0, &1);
+    assert_eq!(page.entities, vec![&env, (first, vec![&env, position.clone()])]);
+    assert_eq!(page.next, Some(second.index));
+    let page = client.list_entities(&world, &page.next.unwrap(), &1);
+    assert_eq!(page.entities.get_unchecked(0).0, second);
+    assert_eq!(page.next, None);
+
+    // systems are listed in address order
+    let (a, b) = if render < movement {
+        ((render, 0), (movement, 1))
+    } else {
+        ((movement, 1), (render, 0))
+    };
+    let page = client.list_systems(&world, &0, &1);
+    let info = client.get_world(&world).systems.get_unchecked(a.0.clone());
+    assert_eq!(info.query, Bitmap::from_bit(&env, a.1));
+    assert_eq!(page.systems, vec![&env, (a.0, info)]);
+    assert_eq!(page.next, Some(1));
+    let page = client.list_systems(&world, &1, &1);
+    let info = client.get_world(&world).systems.get_unchecked(b.0.clone());
+    assert_eq!(info.query, Bitmap::from_bit(&env, b.1));
+    assert_eq!(page.systems, vec![&env, (b.0, info)]);
+    assert_eq!(page.next, None);
+
+    let page = client.list_components(&world, &0, &10);
+    assert_eq!(page.components, vec![&env, (0, position), (1, velocity.clone())]);
+    assert_eq!(page.next, None);
+    let page = client.list_components(&world, &1, &10);
+    assert_eq!(page.components, vec![&env, (1, velocity.clone())]);
+
+    // a page always moves on by at least one result
+    let page = client.list_entities(&world, &0, &0);
+    assert_eq!(page.entities.len(), 1);
+    assert_eq!(page.next, Some(second.index));
+    let (everything, start) = (Bitmap::new(&env), QueryCursor::Start);
+    let page = client.query(&world, &everything, &everything, &Filter::Any, &start, &0);
+    assert_eq!(page.entities.get_unchecked(0).0, first);
+    let signature = client.get_entity(&world, &second).bitmap;
+    assert_eq!(page.next, QueryCursor::At(signature, second.index));
+    assert_eq!(client.list_systems(&world, &0, &0).next, Some(1));
+    let page = client.list_components(&world, &1, &0);
+    assert_eq!(page.components, vec![&env, (1, velocity)]);
+    assert_eq!(page.next, None);
+}
+
+#[test]
+fn pages_keep_their_place_between_mutations() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let owner = Address::generate(&env);
+    let position = component(&env, &client, world);
+    let velocity = component(&env, &client, world);
+    let ids = |entities: Vec<(Entity, Vec<Address>)>| {
+        let mut ids = Vec::new(&env);
+        for (entity, _) in entities.iter() {
+            ids.push_back(entity);
+        }
+        ids
+    };
+
+    let mut spawned = Vec::new(&env);
+    for _ in 0..4 {
+        spawned.push_back(client.spawn(&world, &owner, &vec![&env, position.clone()]));
+    }
+    let [one, two, three, four] = [0, 1, 2, 3].map(|at| spawned.get_unchecked(at));
+
+    // despawning an entity already listed doesn't move the ones still to come
+    let page = client.list_entities(&world, &0, &2);
+    assert_eq!(ids(page.entities), vec![&env, one, two]);
+    client.despawn(&world, &one);
+    let page = client.list_entities(&world, &page.next.unwrap(), &2);
+    assert_eq!(ids(page.entities), vec![&env, three, four]);
+    assert_eq!(page.next, None);
+
+    let (with, none) = (Bitmap::from_bit(&env, 0), Bitmap::new(&env));
+    let page = client.query(&world, &with, &none, &Filter::Any, &QueryCursor::Start, &1);
+    assert_eq!(ids(page.entities), vec![&env, two]);
+    client.despawn(&world, &two);
+    client.insert_components(&world, &four, &vec![&env, velocity]);
+    let page = client.query(&world, &with, &none, &Filter::Any, &page.next, &2);
+    assert_eq!(ids(page.entities), vec![&env, three, four]);
+    assert_eq!(page.next, QueryCursor::Done);
+}
+
+#[test]
+fn entities_live_in_their_own_entries() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let owner = Address::generate(&env);
+    let position = component(&env, &client, world);
+
+    let entity = client.spawn(&world, &owner, &vec![&env, position.clone()]);
+
+    env.as_contract(&client.address, || {
+        let record: EntityRecord = env
+            .storage()
+            .persistent()
+            .get(&DataKey::Entity(world, entity.index))
+            .unwrap();
+        assert_eq!(record.components, vec![&env, position]);
+    });
+
+    client.despawn(&world, &entity);
+    env.as_contract(&client.address, || {
+        assert!(!env.storage().persistent().has(&DataKey::Entity(world, entity.index)));
+    });
+}
+
+#[test]
+fn archetypes_follow_entity_signatures() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let owner = Address::generate(&env);
+    let position = component(&env, &client, world);
+    let velocity = component(&env, &client, world);
+
+    let first = client.spawn(&world, &owner, &vec![&env, position.clone()]);
+    let second = client.spawn(&world, &owner, &vec![&env, position.clone()]);
+    let third = client.spawn(&world, &owner, &vec![&env, position.clone()]);
+    let only_position = Bitmap::from_bit(&env, 0);
+    assert_eq!(client.get_world(&world).archetypes, vec![&env, only_position.clone()]);
+
+    // members are kept as bits of their slots, each chunk of slots in an entry of its own
+    client.insert_components(&world, &second, &vec![&env, velocity.clone()]);
+    let both = client.get_entity(&world, &second).bitmap;
+    assert_eq!(
+        client.get_world(&world).archetypes,
+        vec![&env, only_position.clone(), both.clone()]
+    );
+    env.as_contract(&client.address, || {
+        let members = |signature: &Bitmap| {
+            let slots = Slots::Archetype(world, signature.clone());
+            slots.iter(&env, 0).collect::<std::vec::Vec<_>>()
+        };
+        assert_eq!(members(&only_position), [first.index, third.index]);
+        assert_eq!(members(&both), [second.index]);
+        let chunk = DataKey::ArchetypeChunk(world, only_position.clone(), 0);
+        assert_eq!(env.storage().persistent().get(&chunk), Some(0b1010u128));
+    });
+
+    // queries only look at archetypes holding every requested component
+    let with = Bitmap::from_bit(&env, 1);
+    let start = QueryCursor::Start;
+    let page = client.query(&world, &with, &Bitmap::new(&env), &Filter::Any, &start, &10);
+    assert_eq!(
+        page.entities,
+        vec![&env, (second, vec![&env, position.clone(), velocity])]
+    );
+
+    // empty archetypes are dropped
+    client.despawn(&world, &first);
+    client.despawn(&world, &third);
+    assert_eq!(client.get_world(&world).archetypes, vec![&env, both.clone()]);
+    client.remove_components(&world, &second, &vec![&env, position]);
+    assert_eq!(
+        client.get_world(&world).archetypes,
+        vec![&env, Bitmap::from_bit(&env, 1)]
+    );
+    env.as_contract(&client.address, || {
+        let chunk = DataKey::ArchetypeChunk(world, both.clone(), 0);
+        assert!(!env.storage().persistent().has(&chunk));
+        assert!(!env.storage().persistent().has(&DataKey::Archetype(world, both)));
+    });
+}
+
+/// The ledger a contract data entry lives until, read back from the ledger snapshot
+fn live_until(env: &Env, contract: &Address, key: xdr::ScVal) -> Option<u32> {
+    let contract = xdr::ScAddress::try_from(contract).unwrap();
+    env.to_ledger_snapshot()
+        .ledger_entries
+        .into_iter()
+        .find_map(|(ledger_key, (_, live_until))| match *ledger_key {
+            xdr::LedgerKey::ContractData(data) if data.contract == contract && data.key == key => {
+                live_until
+            }
+            _ => None,
+        })
+}
+
+#[test]
+fn ttl_is_extended_on_access() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let owner = Address::generate(&env);
+    let position = component(&env, &client, world);
+
+    let config = WorldConfig {
+        ttl_threshold: 200_000,
+        ttl_extend_to: 300_000,
+    };
+    client.set_config(&world, &config);
+    assert_eq!(client.get_config(&world), config);
+
+    let entity = client.spawn(&world, &owner, &vec![&env, position]);
+    let entity_key: Val = DataKey::Entity(world, entity.index).into_val(&env);
+    let entity_key = xdr::ScVal::try_from_val(&env, &entity_key).unwrap();
+    let instance_key = xdr::ScVal::LedgerKeyContractInstance;
+    let admin_key: Val = DataKey::Admin(world).into_val(&env);
+    let admin_key = xdr::ScVal::try_from_val(&env, &admin_key).unwrap();
+    assert_eq!(live_until(&env, &client.address, entity_key.clone()), Some(300_000));
+    assert_eq!(live_until(&env, &client.address, instance_key.clone()), Some(300_000));
+
+    // close to expiring, touching the world bumps the instance and the entries of the world but
+    // leaves entities alone
+    env.ledger().with_mut(|ledger| ledger.sequence_number = 150_000);
+    client.world_info(&world);
+    assert_eq!(live_until(&env, &client.address, instance_key), Some(450_000));
+    assert_eq!(live_until(&env, &client.address, admin_key), Some(450_000));
+    assert_eq!(live_until(&env, &client.address, entity_key.clone()), Some(300_000));
+
+    // anyone can keep an entity alive
+    client.extend_ttl(&world, &vec![&env, entity]);
+    assert_eq!(live_until(&env, &client.address, entity_key), Some(450_000));
+
+    assert_eq!(
+        client.try_set_config(&world, &WorldConfig {
+            ttl_threshold: 2,
+            ttl_extend_to: 1,
+        }),
+        Err(Ok(Error::InvalidConfig))
+    );
+}
+
+mod count_system {
+    use crate::{Command, Entity};
+    use soroban_sdk::{contract, contractimpl, symbol_short, Address, Bytes, Env, Map, Symbol, Vec};
+
+    /// A system that tags each entity it is handed with the number of entities it saw
+    #[contract]
+    pub struct CountSystem;
+
+    #[contractimpl]
+    impl CountSystem {
+        pub fn init(env: Env, component: Address) {
+            env.storage().instance().set(&symbol_short!("component"), &component);
+        }
+
+        pub fn run(
+            env: Env,
+            _world: Address,
+            entities: Vec<Entity>,
+            _resources: Map<Symbol, Bytes>,
+        ) -> Vec<Command> {
+            let component: Address = env
+                .storage()
+                .instance()
+                .get(&symbol_short!("component"))
+                .unwrap();
+            let count = Bytes::from_array(&env, &[entities.len() as u8]);
+            let mut commands = Vec::new(&env);
+            for entity in entities.iter() {
+                commands.push_back(Command::SetComponent(entity, component.clone(), count.clone()));
+            }
+            commands
+        }
+    }
+}
+
+mod reap_system {
+    use crate::{Command, Entity};
+    use soroban_sdk::{contract, contractimpl, Address, Bytes, Env, Map, Symbol, Vec};
+
+    /// A system that despawns every entity it is handed
+    #[contract]
+    pub struct ReapSystem;
+
+    #[contractimpl]
+    impl ReapSystem {
+        pub fn run(
+            env: Env,
+            _world: Address,
+            entities: Vec<Entity>,
+            _resources: Map<Symbol, Bytes>,
+        ) -> Vec<Command> {
+            let mut commands = Vec::new(&env);
+            for entity in entities.iter() {
+                commands.push_back(Command::Despawn(entity));
+            }
+            commands
+        }
+    }
+}
+
+mod script_system {
+    use crate::{Command, Entity};
+    use soroban_sdk::{
+        contract, contractimpl, symbol_short, Address, Bytes, Env, Map, Symbol, Vec,
+    };
+
+    /// A system handing back the same commands on every run
+    #[contract]
+    pub struct ScriptSystem;
+
+    #[contractimpl]
+    impl ScriptSystem {
+        pub fn init(env: Env, commands: Vec<Command>) {
+            env.storage().instance().set(&symbol_short!("commands"), &commands);
+        }
+
+        pub fn run(
+            env: Env,
+            _world: Address,
+            _entities: Vec<Entity>,
+            _resources: Map<Symbol, Bytes>,
+        ) -> Vec<Command> {
+            env.storage().instance().get(&symbol_short!("commands")).unwrap()
+        }
+    }
+}
+
+mod log_hook {
+    use crate::{Command, Entity};
+    use soroban_sdk::{contract, contractimpl, symbol_short, vec, Address, Env, Symbol, Vec};
+
+    /// A hook logging every call, handing back the same commands on every `on_add` and, if
+    /// `touch` is set, inserting `touch` into the entity it runs for as well
+    #[contract]
+    pub struct LogHook;
+
+    #[contractimpl]
+    impl LogHook {
+        pub fn init(env: Env, commands: Vec<Command>, touch: Option<Address>) {
+            env.storage().instance().set(&symbol_short!("commands"), &commands);
+            env.storage().instance().set(&symbol_short!("touch"), &touch);
+        }
+
+        pub fn log(env: Env) -> Vec<(Symbol, Entity, Address)> {
+            let log = env.storage().instance().get(&symbol_short!("log"));
+            log.unwrap_or(Vec::new(&env))
+        }
+
+        pub fn on_add(
+            env: Env,
+            _world: Address,
+            entity: Entity,
+            component: Address,
+        ) -> Vec<Command> {
+            Self::record(&env, symbol_short!("add"), entity, component);
+            let storage = env.storage().instance();
+            let mut commands: Vec<Command> = storage.get(&symbol_short!("commands")).unwrap();
+            let touch: Option<Address> = storage.get(&symbol_short!("touch")).unwrap();
+            if let Some(touch) = touch {
+                commands.push_back(Command::Insert(entity, vec![&env, touch]));
+            }
+            commands
+        }
+
+        pub fn on_remove(
+            env: Env,
+            _world: Address,
+            entity: Entity,
+            component: Address,
+        ) -> Vec<Command> {
+            Self::record(&env, symbol_short!("remove"), entity, component);
+            Vec::new(&env)
+        }
+    }
+
+    impl LogHook {
+        fn record(env: &Env, kind: Symbol, entity: Entity, component: Address) {
+            let mut log = Self::log(env.clone());
+            log.push_back((kind, entity, component));
+            env.storage().instance().set(&symbol_short!("log"), &log);
+        }
+    }
+}
+
+mod round_system {
+    use crate::{Command, Entity};
+    use soroban_sdk::{
+        contract, contractimpl, symbol_short, vec, Address, Bytes, Env, Map, Symbol, Vec,
+    };
+
+    /// A system moving the `round` resource, a single byte, on by one
+    #[contract]
+    pub struct RoundSystem;
+
+    #[contractimpl]
+    impl RoundSystem {
+        pub fn run(
+            env: Env,
+            _world: Address,
+            _entities: Vec<Entity>,
+            resources: Map<Symbol, Bytes>,
+        ) -> Vec<Command> {
+            let round = resources
+                .get(symbol_short!("round"))
+                .map_or(0, |round| round.get_unchecked(0));
+            let next = Bytes::from_array(&env, &[round + 1]);
+            vec![&env, Command::SetResource(symbol_short!("round"), next)]
+        }
+    }
+}
+
+#[test]
+fn tick_runs_systems_on_matching_entities() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let owner = Address::generate(&env);
+    let position = component(&env, &client, world);
+    let dead = component(&env, &client, world);
+
+    let counter = env.register_contract(None, count_system::CountSystem);
+    count_system::CountSystemClient::new(&env, &counter).init(&position);
+    let reaper = env.register_contract(None, reap_system::ReapSystem);
+
+    let first = client.spawn(&world, &owner, &vec![&env, position.clone()]);
+    let second = client.spawn(&world, &owner, &vec![&env, position.clone()]);
+    let doomed = client.spawn(&world, &owner, &vec![&env, dead]);
+
+    add_system(&env, &client, world, &counter, &Bitmap::from_bit(&env, 0));
+    add_system(&env, &client, world, &reaper, &Bitmap::from_bit(&env, 1));
+    client.tick(&world);
+
+    let two = Bytes::from_array(&env, &[2]);
+    assert_eq!(client.get_component(&world, &first, &position), Some(two.clone()));
+    assert_eq!(client.get_component(&world, &second, &position), Some(two));
+    assert_eq!(client.try_get_entity(&world, &doomed), Err(Ok(Error::EntityNotFound)));
+    assert_eq!(owned(&env, &client, world, &owner), vec![&env, first, second]);
+}
+
+#[test]
+fn ticks_fit_the_default_budget() {
+    let env = Env::default();
+    env.budget().reset_unlimited();
+    let (client, world) = setup(&env);
+    let owner = Address::generate(&env);
+    let position = component(&env, &client, world);
+    let counter = env.register_contract(None, count_system::CountSystem);
+    count_system::CountSystemClient::new(&env, &counter).init(&position);
+    add_system(&env, &client, world, &counter, &Bitmap::from_bit(&env, 0));
+    for _ in 0..100 {
+        client.spawn(&world, &owner, &vec![&env, position.clone()]);
+    }
+
+    // a batch writing every entity is journaled in memory, not in ledger entries
+    env.budget().reset_default();
+    client.tick(&world);
+    let last = client.list_entities(&world, &100, &1).entities.get_unchecked(0).0;
+    let value = client.get_component(&world, &last, &position);
+    assert_eq!(value, Some(Bytes::from_array(&env, &[100])));
+}
+
+#[test]
+fn systems_run_in_schedule_order() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let query = Bitmap::from_bit(&env, 0);
+    let none = Vec::new(&env);
+    let (input, physics, scoring) = (
+        symbol_short!("input"),
+        symbol_short!("physics"),
+        symbol_short!("scoring"),
+    );
+
+    assert_eq!(
+        client.try_add_system(
+            &world,
+            &Address::generate(&env),
+            &query,
+            &input,
+            &none,
+            &none,
+            &Detect::All
+        ),
+        Err(Ok(Error::StageNotFound))
+    );
+    client.set_stages(&world, &vec![&env, input.clone(), physics.clone(), scoring.clone()]);
+    assert_eq!(
+        client.try_set_stages(&world, &vec![&env, input.clone(), input.clone()]),
+        Err(Ok(Error::InvalidConfig))
+    );
+
+    let score = Address::generate(&env);
+    let read = Address::generate(&env);
+    let collide = Address::generate(&env);
+    let integrate = Address::generate(&env);
+    client.add_system(&world, &score, &query, &scoring, &none, &none, &Detect::All);
+    client.add_system(&world, &collide, &query, &physics, &none, &none, &Detect::All);
+    client.add_system(
+        &world,
+        &integrate,
+        &query,
+        &physics,
+        &vec![&env, collide.clone()],
+        &none,
+        &Detect::All,
+    );
+    client.add_system(&world, &read, &query, &input, &none, &none, &Detect::All);
+    assert_eq!(
+        client.schedule(&world),
+        vec![
+            &env,
+            read.clone(),
+            integrate.clone(),
+            collide.clone(),
+            score.clone()
+        ]
+    );
+
+    // a constraint against the stage order or one closing a loop is refused
+    let late = Address::generate(&env);
+    assert_eq!(
+        client.try_add_system(
+            &world,
+            &late,
+            &query,
+            &scoring,
+            &vec![&env, read.clone()],
+            &none,
+            &Detect::All
+        ),
+        Err(Ok(Error::SystemCycle))
+    );
+    assert_eq!(
+        client.try_add_system(
+            &world,
+            &late,
+            &query,
+            &physics,
+            &vec![&env, integrate.clone()],
+            &vec![&env, collide.clone()],
+            &Detect::All
+        ),
+        Err(Ok(Error::SystemCycle))
+    );
+    assert_eq!(client.get_world(&world).systems.len(), 4);
+
+    // stages in use can't be dropped
+    assert_eq!(
+        client.try_set_stages(&world, &vec![&env, input.clone(), physics.clone()]),
+        Err(Ok(Error::StageNotFound))
+    );
+
+    // nor reordered against the constraints of the systems running in them
+    let tally = Address::generate(&env);
+    let reads = vec![&env, read];
+    client.add_system(&world, &tally, &query, &scoring, &none, &reads, &Detect::All);
+    assert_eq!(
+        client.try_set_stages(&world, &vec![&env, scoring.clone(), physics.clone(), input.clone()]),
+        Err(Ok(Error::SystemCycle))
+    );
+    assert_eq!(client.get_world(&world).stages, vec![&env, input, physics, scoring]);
+}
+
+#[test]
+fn command_batches_apply_whole_or_not_at_all() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let owner = Address::generate(&env);
+    let position = component(&env, &client, world);
+    let velocity = component(&env, &client, world);
+    let entity = client.spawn(&world, &owner, &vec![&env, position.clone()]);
+    let (one, two) = (Bytes::from_array(&env, &[1]), Bytes::from_array(&env, &[2]));
+
+    let valid = env.register_contract(None, script_system::ScriptSystem);
+    script_system::ScriptSystemClient::new(&env, &valid).init(&vec![
+        &env,
+        Command::Spawn(owner.clone(), vec![&env, velocity.clone()]),
+        Command::Insert(entity, vec![&env, velocity.clone()]),
+        Command::SetComponent(entity, velocity.clone(), one.clone()),
+    ]);
+    // the last command touches an entity despawned earlier in the batch
+    let invalid = env.register_contract(None, script_system::ScriptSystem);
+    script_system::ScriptSystemClient::new(&env, &invalid).init(&vec![
+        &env,
+        Command::SetComponent(entity, velocity.clone(), two),
+        Command::Despawn(entity),
+        Command::SetComponent(entity, position.clone(), one.clone()),
+    ]);
+
+    let none = Vec::new(&env);
+    let update = symbol_short!("update");
+    let query = Bitmap::from_bit(&env, 0);
+    let first = vec![&env, invalid.clone()];
+    client.add_system(&world, &valid, &query, &update, &first, &none, &Detect::All);
+    client.add_system(&world, &invalid, &query, &update, &none, &none, &Detect::All);
+    client.tick(&world);
+
+    assert_eq!(client.get_component(&world, &entity, &velocity), Some(one));
+    assert_eq!(client.get_component(&world, &entity, &position), None);
+    assert_eq!(client.world_info(&world).entities, 2);
+    let spawned = owned(&env, &client, world, &owner).get_unchecked(1);
+    assert_eq!(client.get_entity(&world, &spawned).components, vec![&env, velocity]);
+}
+
+#[test]
+fn batches_failing_in_a_hook_are_rolled_back() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let owner = Address::generate(&env);
+    let position = component(&env, &client, world);
+    let player = Address::generate(&env);
+    let item = component(&env, &client, world);
+    let entity = client.spawn(&world, &owner, &vec![&env, position.clone()]);
+
+    // the hook of `player` touches the entity it runs for, which only fails once it is applied
+    let hook = env.register_contract(None, log_hook::LogHook);
+    log_hook::LogHookClient::new(&env, &hook).init(&Vec::new(&env), &Some(item));
+    client.set_hooks(&world, &player, &Some(hook), &None);
+    let system = env.register_contract(None, script_system::ScriptSystem);
+    script_system::ScriptSystemClient::new(&env, &system).init(&vec![
+        &env,
+        Command::SetComponent(entity, position.clone(), Bytes::from_array(&env, &[1])),
+        Command::Insert(entity, vec![&env, player]),
+    ]);
+    add_system(&env, &client, world, &system, &Bitmap::from_bit(&env, 0));
+
+    let mut seen = env.events().all().len();
+    client.tick(&world);
+    assert_eq!(
+        events_since(&env, &mut seen),
+        vec![
+            &env,
+            (
+                client.address.clone(),
+                (symbol_short!("system"), symbol_short!("dropped"), world, system.clone())
+                    .into_val(&env),
+                (Error::Reentrant as u32).into_val(&env),
+            ),
+            (
+                client.address.clone(),
+                (symbol_short!("system"), symbol_short!("run"), world, system.clone())
+                    .into_val(&env),
+                1u32.into_val(&env),
+            ),
+        ]
+    );
+    assert_eq!(client.get_entity(&world, &entity).components, vec![&env, position.clone()]);
+    assert_eq!(client.get_component(&world, &entity, &position), None);
+    assert_eq!(client.get_world(&world).systems.get_unchecked(system).last_run, 1);
+
+    // the entity isn't left guarded
+    client.set_component(&world, &entity, &position, &Bytes::from_array(&env, &[2]));
+}
+
+/// The events published since the first `seen` of them, moving `seen` past them
+fn events_since(env: &Env, seen: &mut u32) -> Vec<(Address, Vec<Val>, Val)> {
+    let all = env.events().all();
+    let events = all.slice(*seen..);
+    *seen = all.len();
+    events
+}
+
+#[test]
+fn mutations_publish_events() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, Contract);
+    let client = ContractClient::new(&env, &contract_id);
+    let admin = Address::generate(&env);
+    let owner = Address::generate(&env);
+    let position = Address::generate(&env);
+    let velocity = Address::generate(&env);
+    let system = Address::generate(&env);
+    let mut seen = 0;
+
+    let world = client.genesis(&admin, &symbol_short!("Dev"));
+    assert_eq!(
+        events_since(&env, &mut seen),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (symbol_short!("world"), symbol_short!("genesis"), world).into_val(&env),
+                (admin, symbol_short!("Dev")).into_val(&env),
+            ),
+        ]
+    );
+
+    client.register_component(&world, &position);
+    client.register_component(&world, &velocity);
+    assert_eq!(
+        events_since(&env, &mut seen),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (symbol_short!("component"), symbol_short!("register"), world, position.clone())
+                    .into_val(&env),
+                0u32.into_val(&env),
+            ),
+            (
+                contract_id.clone(),
+                (symbol_short!("component"), symbol_short!("register"), world, velocity.clone())
+                    .into_val(&env),
+                1u32.into_val(&env),
+            ),
+        ]
+    );
+
+    let entity = client.spawn(&world, &owner, &vec![&env, position.clone()]);
+    let first = Bitmap::from_bit(&env, 0);
+    assert_eq!(
+        events_since(&env, &mut seen),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (symbol_short!("entity"), symbol_short!("spawn"), world, owner.clone())
+                    .into_val(&env),
+                (entity, first.clone()).into_val(&env),
+            ),
+        ]
+    );
+
+    client.insert_components(&world, &entity, &vec![&env, velocity.clone()]);
+    let both = first.union(&Bitmap::from_bit(&env, 1));
+    assert_eq!(
+        events_since(&env, &mut seen),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (symbol_short!("entity"), symbol_short!("insert"), world).into_val(&env),
+                (entity, first.clone(), both.clone()).into_val(&env),
+            ),
+        ]
+    );
+
+    client.remove_components(&world, &entity, &vec![&env, velocity]);
+    assert_eq!(
+        events_since(&env, &mut seen),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (symbol_short!("entity"), symbol_short!("remove"), world).into_val(&env),
+                (entity, both, first.clone()).into_val(&env),
+            ),
+        ]
+    );
+
+    client.despawn(&world, &entity);
+    assert_eq!(
+        events_since(&env, &mut seen),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (symbol_short!("entity"), symbol_short!("despawn"), world, owner).into_val(&env),
+                (entity, first).into_val(&env),
+            ),
+        ]
+    );
+
+    add_system(&env, &client, world, &system, &Bitmap::from_bit(&env, 0));
+    let info = client.get_world(&world).systems.get_unchecked(system.clone());
+    assert_eq!(
+        events_since(&env, &mut seen),
+        vec![
+            &env,
+            (
+                contract_id.clone(),
+                (symbol_short!("system"), symbol_short!("add"), world, system.clone())
+                    .into_val(&env),
+                info.into_val(&env),
+            ),
+        ]
+    );
+    client.remove_system(&world, &system);
+    assert_eq!(
+        events_since(&env, &mut seen),
+        vec![
+            &env,
+            (
+                contract_id,
+                (symbol_short!("system"), symbol_short!("remove"), world, system).into_val(&env),
+                ().into_val(&env),
+            ),
+        ]
+    );
+}
+
+#[test]
+fn admin_and_owner_changes_publish_events() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let owner = Address::generate(&env);
+    let buyer = Address::generate(&env);
+    let position = component(&env, &client, world);
+    let entity = client.spawn(&world, &owner, &vec![&env, position.clone()]);
+    let event = |topics: Vec<Val>, data: Val| vec![&env, (client.address.clone(), topics, data)];
+    let mut seen = env.events().all().len();
+
+    let value = Bytes::from_array(&env, &[1]);
+    client.set_component(&world, &entity, &position, &value);
+    assert_eq!(
+        events_since(&env, &mut seen),
+        event(
+            (symbol_short!("entity"), symbol_short!("set"), world).into_val(&env),
+            (entity, position, value).into_val(&env),
+        )
+    );
+    client.transfer_entity(&world, &entity, &buyer);
+    assert_eq!(
+        events_since(&env, &mut seen),
+        event(
+            (symbol_short!("entity"), symbol_short!("transfer"), world, owner).into_val(&env),
+            (entity, buyer.clone()).into_val(&env),
+        )
+    );
+
+    let config = WorldConfig {
+        ttl_threshold: 10,
+        ttl_extend_to: 20,
+    };
+    client.set_config(&world, &config);
+    assert_eq!(
+        events_since(&env, &mut seen),
+        event(
+            (symbol_short!("world"), symbol_short!("config"), world).into_val(&env),
+            config.into_val(&env),
+        )
+    );
+    let stages = vec![&env, symbol_short!("input"), symbol_short!("update")];
+    client.set_stages(&world, &stages);
+    assert_eq!(
+        events_since(&env, &mut seen),
+        event(
+            (symbol_short!("world"), symbol_short!("stages"), world).into_val(&env),
+            stages.into_val(&env),
+        )
+    );
+
+    let system = env.register_contract(None, round_system::RoundSystem);
+    add_system(&env, &client, world, &system, &Bitmap::new(&env));
+    let resources = vec![&env, symbol_short!("round")];
+    seen = env.events().all().len();
+    client.grant_resources(&world, &system, &resources);
+    assert_eq!(
+        events_since(&env, &mut seen),
+        event(
+            (symbol_short!("system"), symbol_short!("grant"), world, system.clone())
+                .into_val(&env),
+            resources.into_val(&env),
+        )
+    );
+    // the run follows the events of the batch, here the resource it set
+    client.tick(&world);
+    assert_eq!(
+        events_since(&env, &mut seen).slice(1..),
+        event(
+            (symbol_short!("system"), symbol_short!("run"), world, system).into_val(&env),
+            1u32.into_val(&env),
+        )
+    );
+
+    client.set_admin(&world, &buyer);
+    assert_eq!(
+        events_since(&env, &mut seen),
+        event(
+            (symbol_short!("world"), symbol_short!("admin"), world).into_val(&env),
+            buyer.into_val(&env),
+        )
+    );
+}
+
+#[test]
+fn component_hooks_react_to_changes() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let owner = Address::generate(&env);
+    let player = Address::generate(&env);
+    let item = component(&env, &client, world);
+    let other = component(&env, &client, world);
+
+    // every player is handed a starter item
+    let hook = env.register_contract(None, log_hook::LogHook);
+    let log = log_hook::LogHookClient::new(&env, &hook);
+    log.init(&vec![&env, Command::Spawn(owner.clone(), vec![&env, item.clone()])], &None);
+    assert_eq!(client.set_hooks(&world, &player, &Some(hook.clone()), &Some(hook.clone())), 2);
+
+    let first = client.spawn(&world, &owner, &vec![&env, player.clone()]);
+    let starter = owned(&env, &client, world, &owner).get_unchecked(1);
+    assert_eq!(client.get_entity(&world, &starter).components, vec![&env, item.clone()]);
+
+    let second = client.spawn(&world, &owner, &vec![&env, other.clone()]);
+    client.insert_components(&world, &second, &vec![&env, player.clone(), other.clone()]);
+    client.remove_components(&world, &second, &vec![&env, player.clone()]);
+    client.despawn(&world, &first);
+    assert_eq!(
+        log.log(),
+        vec![
+            &env,
+            (symbol_short!("add"), first, player.clone()),
+            (symbol_short!("add"), second, player.clone()),
+            (symbol_short!("remove"), second, player.clone()),
+            (symbol_short!("remove"), first, player.clone()),
+        ]
+    );
+    // the starter items handed out on spawn and on insert
+    assert_eq!(client.world_info(&world).entities, 3);
+
+    // a hook can't change the entity it runs for
+    log.init(&Vec::new(&env), &Some(item));
+    assert_eq!(
+        client.try_spawn(&world, &owner, &vec![&env, player.clone()]),
+        Err(Ok(Error::Reentrant))
+    );
+
+    client.set_hooks(&world, &player, &None, &None);
+    client.spawn(&world, &owner, &vec![&env, player]);
+    assert_eq!(log.log().len(), 4);
+}
+
+#[test]
+fn change_detection_filters() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let owner = Address::generate(&env);
+    let position = component(&env, &client, world);
+    let velocity = component(&env, &client, world);
+    let still = client.spawn(&world, &owner, &vec![&env, position.clone()]);
+
+    // the system only sees what changed since it last ran, leaving out its own writes
+    let counter = env.register_contract(None, count_system::CountSystem);
+    count_system::CountSystemClient::new(&env, &counter).init(&position);
+    let (stage, none) = (symbol_short!("update"), Vec::new(&env));
+    let query = Bitmap::from_bit(&env, 0);
+    client.add_system(&world, &counter, &query, &stage, &none, &none, &Detect::Changed);
+    client.tick(&world);
+    client.tick(&world);
+    let one = Bytes::from_array(&env, &[1]);
+    assert_eq!(client.get_component(&world, &still, &position), Some(one));
+
+    let moving = client.spawn(&world, &owner, &vec![&env, position.clone(), velocity.clone()]);
+    client.set_component(&world, &still, &position, &Bytes::from_array(&env, &[9]));
+    client.tick(&world);
+    client.tick(&world);
+    let two = Bytes::from_array(&env, &[2]);
+    assert_eq!(client.get_component(&world, &still, &position), Some(two.clone()));
+    assert_eq!(client.get_component(&world, &moving, &position), Some(two));
+
+    let now = client.world_info(&world).clock;
+    let late = client.spawn(&world, &owner, &vec![&env, velocity.clone()]);
+    client.set_component(&world, &still, &position, &Bytes::from_array(&env, &[3]));
+    let (everything, nothing) = (Bitmap::new(&env), Bitmap::new(&env));
+    let start = QueryCursor::Start;
+    let ids = |page: QueryPage| {
+        let mut entities = Vec::new(&env);
+        for (entity, _) in page.entities.iter() {
+            entities.push_back(entity);
+        }
+        entities
+    };
+
+    let added = Filter::AddedSince(now);
+    let page = client.query(&world, &everything, &nothing, &added, &start, &10);
+    assert_eq!(ids(page), vec![&env, late]);
+    let page = client.query(&world, &query, &nothing, &added, &start, &10);
+    assert_eq!(ids(page), Vec::new(&env));
+
+    let changed = Filter::ChangedSince(now);
+    let page = client.query(&world, &query, &nothing, &changed, &start, &10);
+    assert_eq!(ids(page), vec![&env, still]);
+    let page = client.query(&world, &everything, &nothing, &changed, &start, &1);
+    let signature = client.get_entity(&world, &late).bitmap;
+    assert_eq!(page.next, QueryCursor::At(signature, late.index));
+    let cursor = page.next.clone();
+    assert_eq!(ids(page), vec![&env, still]);
+    let page = client.query(&world, &everything, &nothing, &changed, &cursor, &1);
+    assert_eq!(page.next, QueryCursor::Done);
+    assert_eq!(ids(page), vec![&env, late]);
+}
+
+#[test]
+fn resources_are_shared_with_systems() {
+    let env = Env::default();
+    let (client, world) = setup(&env);
+    let admin = client.get_admin(&world);
+    let round = symbol_short!("round");
+    let byte = |value: u8| Some(Bytes::from_array(&env, &[value]));
+
+    assert_eq!(client.get_resource(&world, &round), None);
+    client.set_resource(&world, &round, &Bytes::from_array(&env, &[1]));
+    assert_eq!(env.auths()[0].0, admin);
+    assert_eq!(client.get_resource(&world, &round), byte(1));
+
+    // systems only write the resources they were granted
+    let system = env.register_contract(None, round_system::RoundSystem);
+    add_system(&env, &client, world, &system, &Bitmap::new(&env));
+    client.tick(&world);
+    assert_eq!(client.get_resource(&world, &round), byte(1));
+
+    client.grant_resources(&world, &system, &vec![&env, round.clone()]);
+    client.tick(&world);
+    client.tick(&world);
+    assert_eq!(client.get_resource(&world, &round), byte(3));
+
+    client.remove_resource(&world, &round);
+    assert_eq!(client.get_resource(&world, &round), None);
+    assert_eq!(client.try_remove_resource(&world, &round), Err(Ok(Error::ResourceNotFound)));
+    assert_eq!(
+        client.try_grant_resources(&world, &Address::generate(&env), &vec![&env, round]),
+        Err(Ok(Error::SystemNotFound))
+    );
+}